@@ -0,0 +1,7 @@
+//! Prints the Wireshark Lua dissector for the packet wire format.
+//!
+//! `cargo run --example wireshark_dissector > ~/.local/lib/wireshark/plugins/protodongers.lua`
+
+fn main() {
+    print!("{}", protodongers::wireshark::lua_dissector());
+}
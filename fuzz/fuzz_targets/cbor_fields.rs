@@ -0,0 +1,20 @@
+#![no_main]
+
+//! Decodes the payload types whose fields go through the `serde_cbor_with` and
+//! `heapless_str32_cbor` helpers directly, without the `Packet` envelope.
+
+use libfuzzer_sys::fuzz_target;
+use protodongers::{AccelReport, CombinedMarkersReport, PocMarkersReport, Props};
+use protodongers_fuzz::cbor_roundtrip;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, data)) = data.split_first() else {
+        return;
+    };
+    match selector % 4 {
+        0 => cbor_roundtrip::<CombinedMarkersReport>(data, |_| true),
+        1 => cbor_roundtrip::<PocMarkersReport>(data, |_| true),
+        2 => cbor_roundtrip::<AccelReport>(data, |_| true),
+        _ => cbor_roundtrip::<Props>(data, |_| true),
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protodongers::Packet;
+use protodongers_fuzz::{cbor_roundtrip, is_exact};
+
+fuzz_target!(|data: &[u8]| {
+    cbor_roundtrip::<Packet>(data, |pkt| is_exact(&pkt.data, false));
+});
@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protodongers::Packet;
+use protodongers_fuzz::is_exact;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(pkt) = Packet::parse(&mut &data[..]) else {
+        return;
+    };
+    let mut encoded = Vec::new();
+    pkt.serialize_to_vec(&mut encoded);
+    assert_eq!(encoded.len(), pkt.serialized_len());
+
+    let bytes = &mut &encoded[..];
+    let reparsed = Packet::parse(bytes).expect("parse serialized packet");
+    assert!(bytes.is_empty());
+    assert_eq!(reparsed.id, pkt.id);
+    assert_eq!(u8::from(reparsed.ty()), u8::from(pkt.ty()));
+
+    let mut reencoded = Vec::new();
+    reparsed.serialize_to_vec(&mut reencoded);
+    if is_exact(&pkt.data, true) {
+        assert_eq!(encoded, reencoded);
+    }
+});
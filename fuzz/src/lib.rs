@@ -0,0 +1,36 @@
+use minicbor::{CborLen, Decode, Encode};
+use protodongers::{GeneralConfig, PacketData};
+
+/// Whether re-encoding `data` reproduces its bytes exactly. Camera and stereo
+/// configs go through float conversions, and the binary `AccelReport` is
+/// fixed-point, so those only have to decode again.
+pub fn is_exact(data: &PacketData, binary: bool) -> bool {
+    match data {
+        PacketData::WriteConfig(c) | PacketData::ReadConfigResponse(c) => !matches!(
+            c,
+            GeneralConfig::CameraModelNf(_)
+                | GeneralConfig::CameraModelWf(_)
+                | GeneralConfig::StereoIso(_)
+        ),
+        PacketData::AccelReport(_) => !binary,
+        _ => true,
+    }
+}
+
+/// Decodes `data` as a `T`, then checks that encoding it matches its `CborLen`
+/// and that decoding and re-encoding that output is stable.
+pub fn cbor_roundtrip<T>(data: &[u8], exact: impl Fn(&T) -> bool)
+where
+    T: for<'b> Decode<'b, ()> + Encode<()> + CborLen<()>,
+{
+    let Ok(value) = minicbor::decode::<T>(data) else {
+        return;
+    };
+    let encoded = minicbor::to_vec(&value).expect("encode decoded value");
+    assert_eq!(minicbor::len(&value), encoded.len());
+    let decoded = minicbor::decode::<T>(&encoded).expect("decode re-encoded value");
+    let reencoded = minicbor::to_vec(&decoded).expect("encode re-decoded value");
+    if exact(&value) {
+        assert_eq!(encoded, reencoded);
+    }
+}
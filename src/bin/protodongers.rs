@@ -0,0 +1,360 @@
+//! Decodes, encodes and inspects protodongers messages from the command line.
+//!
+//! ```text
+//! protodongers decode 0a000b0710000000000000000008000000000000
+//! protodongers decode --type mux --encoding base64 ggSA
+//! echo '{"id":1,"data":{"ReadVersion":[]}}' | protodongers encode
+//! protodongers dump session.pdcapt
+//! ```
+
+use std::{
+    error::Error,
+    fmt::Debug,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use clap::{Parser, Subcommand, ValueEnum};
+use protodongers::{
+    Packet,
+    capture::{self, CaptureReader, RecordBody},
+    control::{device::DeviceMsg, usb_mux::UsbMuxCtrlMsg},
+    mux::{MuxMsg, Uuid},
+    pcapng::{Direction, Message, PcapngReader},
+};
+use serde_json::json;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Decodes, encodes and inspects protodongers messages.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Decodes bytes into messages and prints them.
+    Decode {
+        /// What the bytes hold.
+        #[arg(short = 't', long = "type", value_name = "TYPE")]
+        #[arg(value_enum, default_value_t = Kind::Packet)]
+        kind: Kind,
+        #[arg(short, long, value_enum, default_value_t = Encoding::Hex)]
+        encoding: Encoding,
+        /// Prints JSON, in the form `encode` takes, instead of Rust debug
+        /// output.
+        #[arg(long)]
+        json: bool,
+        /// The bytes, read from stdin if omitted.
+        data: Option<String>,
+    },
+    /// Encodes JSON messages into bytes.
+    Encode {
+        /// What to encode the messages as.
+        #[arg(short = 't', long = "type", value_name = "TYPE")]
+        #[arg(value_enum, default_value_t = Kind::Packet)]
+        kind: Kind,
+        #[arg(short, long, value_enum, default_value_t = Encoding::Hex)]
+        encoding: Encoding,
+        /// One or more JSON messages, read from stdin if omitted.
+        json: Option<String>,
+    },
+    /// Prints the records of a capture or pcapng file.
+    Dump {
+        /// Prints a JSON object per line instead of Rust debug output.
+        #[arg(long)]
+        json: bool,
+        path: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Kind {
+    /// Packets in their binary wire format.
+    Packet,
+    /// Packets in CBOR.
+    PacketCbor,
+    /// Mux messages in CBOR.
+    Mux,
+    /// USB mux control messages in CBOR.
+    UsbMuxCtrl,
+    /// Device control messages in CBOR.
+    Device,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Encoding {
+    /// Hex digits, optionally separated by whitespace, `:` or `-`.
+    Hex,
+    Base64,
+    /// Raw bytes, on stdin or stdout only.
+    Binary,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let out = &mut io::stdout().lock();
+    let result = match cli.command {
+        Command::Decode {
+            kind,
+            encoding,
+            json,
+            data,
+        } => read_bytes(encoding, data).and_then(|bytes| decode(out, kind, &bytes, json)),
+        Command::Encode {
+            kind,
+            encoding,
+            json,
+        } => read_text(json)
+            .and_then(|json| encode(kind, &json))
+            .and_then(|bytes| write_bytes(out, encoding, &bytes)),
+        Command::Dump { json, path } => dump(out, &path, json),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        // The output was piped into something like `head` that has had enough.
+        Err(e)
+            if e.downcast_ref::<io::Error>()
+                .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe) =>
+        {
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn read_text(arg: Option<String>) -> Result<String> {
+    match arg {
+        Some(s) => Ok(s),
+        None => Ok(io::read_to_string(io::stdin())?),
+    }
+}
+
+fn read_bytes(encoding: Encoding, arg: Option<String>) -> Result<Vec<u8>> {
+    match encoding {
+        Encoding::Hex => parse_hex(&read_text(arg)?),
+        Encoding::Base64 => {
+            let text: String = read_text(arg)?.split_whitespace().collect();
+            Ok(BASE64.decode(text)?)
+        }
+        Encoding::Binary => {
+            if arg.is_some() {
+                return Err("binary input is read from stdin".into());
+            }
+            let mut bytes = Vec::new();
+            io::stdin().read_to_end(&mut bytes)?;
+            Ok(bytes)
+        }
+    }
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    let text = text.trim();
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    let digits: Vec<u8> = text
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace() && !matches!(b, b':' | b'-'))
+        .collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err("odd number of hex digits".into());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair)?;
+            Ok(u8::from_str_radix(pair, 16)?)
+        })
+        .collect()
+}
+
+fn write_bytes(out: &mut impl Write, encoding: Encoding, bytes: &[u8]) -> Result<()> {
+    match encoding {
+        Encoding::Hex => {
+            for b in bytes {
+                write!(out, "{b:02x}")?;
+            }
+            writeln!(out)?;
+        }
+        Encoding::Base64 => writeln!(out, "{}", BASE64.encode(bytes))?,
+        Encoding::Binary => out.write_all(bytes)?,
+    }
+    Ok(())
+}
+
+fn print<T: Debug + serde::Serialize>(out: &mut impl Write, msg: &T, json: bool) -> Result<()> {
+    if json {
+        writeln!(out, "{}", serde_json::to_string_pretty(msg)?)?;
+    } else {
+        writeln!(out, "{msg:#?}")?;
+    }
+    Ok(())
+}
+
+/// Decodes and prints every message in `bytes`.
+fn decode(out: &mut impl Write, kind: Kind, bytes: &[u8], json: bool) -> Result<()> {
+    match kind {
+        Kind::Packet => {
+            let mut bytes = bytes;
+            while !bytes.is_empty() {
+                print(out, &Packet::parse(&mut bytes)?, json)?;
+            }
+            Ok(())
+        }
+        Kind::PacketCbor => decode_cbor::<Packet>(out, bytes, json),
+        Kind::Mux => decode_cbor::<MuxMsg>(out, bytes, json),
+        Kind::UsbMuxCtrl => decode_cbor::<UsbMuxCtrlMsg>(out, bytes, json),
+        Kind::Device => decode_cbor::<DeviceMsg>(out, bytes, json),
+    }
+}
+
+fn decode_cbor<T>(out: &mut impl Write, bytes: &[u8], json: bool) -> Result<()>
+where
+    T: for<'b> minicbor::Decode<'b, ()> + Debug + serde::Serialize,
+{
+    let mut d = minicbor::Decoder::new(bytes);
+    while d.position() < bytes.len() {
+        print(out, &d.decode::<T>()?, json)?;
+    }
+    Ok(())
+}
+
+/// Encodes every message in `json`, back to back.
+fn encode(kind: Kind, json: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    match kind {
+        Kind::Packet => {
+            for pkt in serde_json::Deserializer::from_str(json).into_iter::<Packet>() {
+                pkt?.serialize_to_vec(&mut bytes);
+            }
+        }
+        Kind::PacketCbor => encode_cbor::<Packet>(json, &mut bytes)?,
+        Kind::Mux => encode_cbor::<MuxMsg>(json, &mut bytes)?,
+        Kind::UsbMuxCtrl => encode_cbor::<UsbMuxCtrlMsg>(json, &mut bytes)?,
+        Kind::Device => encode_cbor::<DeviceMsg>(json, &mut bytes)?,
+    }
+    Ok(bytes)
+}
+
+fn encode_cbor<T>(json: &str, bytes: &mut Vec<u8>) -> Result<()>
+where
+    T: minicbor::Encode<()> + serde::de::DeserializeOwned,
+{
+    for msg in serde_json::Deserializer::from_str(json).into_iter::<T>() {
+        bytes.extend(minicbor::to_vec(msg?)?);
+    }
+    Ok(())
+}
+
+/// Prints the records of a capture file, or the frames of a pcapng file.
+fn dump(out: &mut impl Write, path: &Path, json: bool) -> Result<()> {
+    let mut file = BufReader::new(File::open(path)?);
+    if file.fill_buf()?.starts_with(&capture::MAGIC) {
+        dump_capture(out, file, json)
+    } else {
+        dump_pcapng(out, file, json)
+    }
+}
+
+fn dump_capture(out: &mut impl Write, file: impl Read, json: bool) -> Result<()> {
+    let reader = CaptureReader::new(file)?;
+    let header = *reader.header();
+    if json {
+        writeln!(
+            out,
+            "{}",
+            json!({
+                "version": header.version,
+                "product_id": header.product_id,
+                "started_unix_us": header.started_unix_us,
+            })
+        )?;
+    } else {
+        writeln!(
+            out,
+            "# version {:?}, product id {:#06x}, started {}",
+            header.version,
+            header.product_id,
+            format_time(Duration::from_micros(header.started_unix_us)),
+        )?;
+    }
+    for record in reader {
+        let record = record?;
+        if json {
+            let line = match &record.body {
+                RecordBody::Packet(pkt) => json!({ "time_us": record.time_us, "packet": pkt }),
+                RecordBody::Mux(msg) => json!({ "time_us": record.time_us, "mux": msg }),
+            };
+            writeln!(out, "{line}")?;
+        } else {
+            let time = record.time().as_secs_f64();
+            match &record.body {
+                RecordBody::Packet(pkt) => writeln!(out, "{time:>12.6} packet {pkt:?}")?,
+                RecordBody::Mux(msg) => writeln!(out, "{time:>12.6} mux {msg:?}")?,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn dump_pcapng(out: &mut impl Write, file: impl Read, json: bool) -> Result<()> {
+    let reader = PcapngReader::new(file).map_err(|_| "not a capture or pcapng file")?;
+    for frame in reader {
+        let frame = frame?;
+        let direction = frame.direction.map(|d| match d {
+            Direction::Inbound => "inbound",
+            Direction::Outbound => "outbound",
+        });
+        if json {
+            let mut line = json!({
+                "timestamp_us": frame.timestamp.as_micros() as u64,
+                "direction": direction,
+                "dev": frame.dev.map(format_uuid),
+                "transport": frame.transport,
+            });
+            let (key, msg) = match &frame.message {
+                Message::Packet(pkt) => ("packet", serde_json::to_value(pkt)?),
+                Message::Mux(msg) => ("mux", serde_json::to_value(msg)?),
+                Message::UsbMuxCtrl(msg) => ("usb_mux_ctrl", serde_json::to_value(msg)?),
+                Message::Device(msg) => ("device", serde_json::to_value(msg)?),
+            };
+            line[key] = msg;
+            writeln!(out, "{line}")?;
+        } else {
+            let mut prefix = format_time(frame.timestamp);
+            for part in [direction.map(Into::into), frame.dev.map(format_uuid)]
+                .into_iter()
+                .flatten()
+            {
+                prefix.push(' ');
+                prefix.push_str(&part);
+            }
+            match &frame.message {
+                Message::Packet(pkt) => writeln!(out, "{prefix} packet {pkt:?}")?,
+                Message::Mux(msg) => writeln!(out, "{prefix} mux {msg:?}")?,
+                Message::UsbMuxCtrl(msg) => writeln!(out, "{prefix} usb_mux_ctrl {msg:?}")?,
+                Message::Device(msg) => writeln!(out, "{prefix} device {msg:?}")?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Seconds since the Unix epoch.
+fn format_time(since_epoch: Duration) -> String {
+    format!("{:.6}", since_epoch.as_secs_f64())
+}
+
+fn format_uuid(uuid: Uuid) -> String {
+    let [a, b, c, d, e, f] = uuid;
+    format!("{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{f:02x}")
+}
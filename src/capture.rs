@@ -0,0 +1,436 @@
+//! Recording and replaying packet streams.
+//!
+//! A capture file starts with [`MAGIC`], a little-endian `u16` format version
+//! and a [`Header`], followed by [`Record`]s. The header and each record are
+//! CBOR, prefixed with their length as a little-endian `u32`. Records hold the
+//! time the host received them, relative to the start of the capture, so a
+//! [`Replay`] can play a session back with its original timing.
+//!
+//! A capture cut short, say by a crash, reads back up to its last complete
+//! record.
+
+use core::convert::Infallible;
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    vec::Vec,
+};
+
+use crate::{Packet, Version, mux::MuxMsg};
+
+pub const MAGIC: [u8; 6] = *b"PDCAPT";
+pub const FORMAT_VERSION: u16 = 1;
+/// Largest header or record a [`CaptureReader`] accepts.
+pub const MAX_RECORD_LEN: u32 = 64 * 1024;
+
+/// Describes the device a capture was taken from.
+#[derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen, Clone, Copy, Debug)]
+pub struct Header {
+    #[n(0)]
+    pub version: Version,
+    /// A [`ProductId`](crate::ProductId) value.
+    #[n(1)]
+    pub product_id: u16,
+    /// When the capture started, in microseconds since the Unix epoch.
+    #[n(2)]
+    pub started_unix_us: u64,
+}
+
+impl Header {
+    /// A header for a capture starting now.
+    pub fn new(version: Version, product_id: u16) -> Self {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self {
+            version,
+            product_id,
+            started_unix_us: since_epoch.as_micros() as u64,
+        }
+    }
+}
+
+#[derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen, Clone, Debug)]
+pub enum RecordBody {
+    #[n(0)]
+    Packet(#[n(0)] Packet),
+    #[n(1)]
+    Mux(#[n(0)] MuxMsg),
+}
+
+#[derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen, Clone, Debug)]
+pub struct Record {
+    /// When the host received the record, in microseconds since the capture
+    /// started.
+    #[n(0)]
+    pub time_us: u64,
+    #[n(1)]
+    pub body: RecordBody,
+}
+
+impl Record {
+    pub fn time(&self) -> Duration {
+        Duration::from_micros(self.time_us)
+    }
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(io::Error),
+    /// The file does not start with [`MAGIC`].
+    BadMagic,
+    UnsupportedFormat(u16),
+    /// A header or record is longer than [`MAX_RECORD_LEN`].
+    RecordTooLong(u32),
+    /// A [`Timing::Scaled`] factor that is not positive and finite.
+    InvalidSpeed(f64),
+    Encode,
+    Decode,
+}
+
+impl Display for CaptureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use CaptureError as S;
+        match self {
+            S::Io(e) => write!(f, "io error: {e}"),
+            S::BadMagic => write!(f, "not a capture file"),
+            S::UnsupportedFormat(v) => write!(f, "unsupported capture format version {v}"),
+            S::RecordTooLong(len) => write!(f, "record of {len} bytes is too long"),
+            S::InvalidSpeed(speed) => write!(f, "invalid replay speed {speed}"),
+            S::Encode => write!(f, "failed to encode record"),
+            S::Decode => write!(f, "failed to decode record"),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CaptureError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<minicbor::encode::Error<Infallible>> for CaptureError {
+    fn from(_: minicbor::encode::Error<Infallible>) -> Self {
+        Self::Encode
+    }
+}
+
+fn write_cbor<W: Write, T: minicbor::Encode<()>>(
+    w: &mut W,
+    buf: &mut Vec<u8>,
+    v: &T,
+) -> Result<(), CaptureError> {
+    buf.clear();
+    buf.extend_from_slice(&[0; 4]);
+    crate::vec_cbor::encode(v, buf)?;
+    let len = u32::try_from(buf.len() - 4)
+        .ok()
+        .filter(|&len| len <= MAX_RECORD_LEN)
+        .ok_or(CaptureError::Encode)?;
+    buf[..4].copy_from_slice(&len.to_le_bytes());
+    w.write_all(buf)?;
+    Ok(())
+}
+
+/// Writes a capture file.
+#[derive(Debug)]
+pub struct CaptureWriter<W: Write> {
+    inner: W,
+    start: Instant,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Writes the file header. Record times count from now.
+    pub fn new(mut inner: W, header: &Header) -> Result<Self, CaptureError> {
+        inner.write_all(&MAGIC)?;
+        inner.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        let mut buf = Vec::new();
+        write_cbor(&mut inner, &mut buf, header)?;
+        Ok(Self {
+            inner,
+            start: Instant::now(),
+            buf,
+        })
+    }
+
+    /// Records `pkt` as received now.
+    pub fn write_packet(&mut self, pkt: &Packet) -> Result<(), CaptureError> {
+        self.write_body(RecordBody::Packet(pkt.clone()))
+    }
+
+    /// Records `msg` as received now.
+    pub fn write_mux(&mut self, msg: &MuxMsg) -> Result<(), CaptureError> {
+        self.write_body(RecordBody::Mux(msg.clone()))
+    }
+
+    fn write_body(&mut self, body: RecordBody) -> Result<(), CaptureError> {
+        let time_us = self.start.elapsed().as_micros() as u64;
+        self.write_record(&Record { time_us, body })
+    }
+
+    /// Writes `record` with its own time, e.g. when converting a capture.
+    pub fn write_record(&mut self, record: &Record) -> Result<(), CaptureError> {
+        write_cbor(&mut self.inner, &mut self.buf, record)
+    }
+
+    pub fn flush(&mut self) -> Result<(), CaptureError> {
+        Ok(self.inner.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Reads a capture file one record at a time.
+#[derive(Debug)]
+pub struct CaptureReader<R: Read> {
+    inner: R,
+    header: Header,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads and checks the file header.
+    pub fn new(mut inner: R) -> Result<Self, CaptureError> {
+        let mut preamble = [0; MAGIC.len() + 2];
+        inner.read_exact(&mut preamble)?;
+        let (magic, format) = preamble.split_at(MAGIC.len());
+        if magic != MAGIC {
+            return Err(CaptureError::BadMagic);
+        }
+        let format = u16::from_le_bytes([format[0], format[1]]);
+        if format != FORMAT_VERSION {
+            return Err(CaptureError::UnsupportedFormat(format));
+        }
+        let mut buf = Vec::new();
+        if !read_frame(&mut inner, &mut buf)? {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let header = minicbor::decode(&buf).map_err(|_| CaptureError::Decode)?;
+        Ok(Self { inner, header, buf })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The next record, or `None` at the end of the file.
+    pub fn read_record(&mut self) -> Result<Option<Record>, CaptureError> {
+        if !read_frame(&mut self.inner, &mut self.buf)? {
+            return Ok(None);
+        }
+        minicbor::decode(&self.buf)
+            .map(Some)
+            .map_err(|_| CaptureError::Decode)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<Record, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Reads one length-prefixed frame into `buf`. Returns `false` at the end of
+/// the file, including after a partly written length prefix or frame.
+fn read_frame<R: Read>(r: &mut R, buf: &mut Vec<u8>) -> Result<bool, CaptureError> {
+    let mut len = [0; 4];
+    if !read_full(r, &mut len)? {
+        return Ok(false);
+    }
+    let len = u32::from_le_bytes(len);
+    if len > MAX_RECORD_LEN {
+        return Err(CaptureError::RecordTooLong(len));
+    }
+    buf.resize(len as usize, 0);
+    Ok(read_full(r, buf)?)
+}
+
+/// Like [`Read::read_exact`], but returns `false` instead of failing at the
+/// end of the file.
+fn read_full<R: Read>(r: &mut R, mut buf: &mut [u8]) -> io::Result<bool> {
+    while !buf.is_empty() {
+        match r.read(buf) {
+            Ok(0) => return Ok(false),
+            Ok(n) => buf = &mut buf[n..],
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// How fast a [`Replay`] plays records back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    /// With the gaps between records as captured.
+    Original,
+    /// With the gaps between records divided by the given factor, which must
+    /// be positive and finite.
+    Scaled(f64),
+    /// Without waiting.
+    AsFastAsPossible,
+}
+
+/// Plays a capture back, sleeping between records as `timing` asks.
+#[derive(Debug)]
+pub struct Replay<R: Read> {
+    reader: CaptureReader<R>,
+    timing: Timing,
+    /// When the first record was returned, and its offset.
+    start: Option<(Instant, Duration)>,
+}
+
+impl<R: Read> Replay<R> {
+    /// Fails with [`CaptureError::InvalidSpeed`] if `timing` is
+    /// [`Timing::Scaled`] by a factor that is not positive and finite.
+    pub fn new(reader: CaptureReader<R>, timing: Timing) -> Result<Self, CaptureError> {
+        match timing {
+            Timing::Scaled(speed) if !(speed.is_finite() && speed > 0.0) => {
+                Err(CaptureError::InvalidSpeed(speed))
+            }
+            _ => Ok(Self {
+                reader,
+                timing,
+                start: None,
+            }),
+        }
+    }
+
+    pub fn header(&self) -> &Header {
+        self.reader.header()
+    }
+
+    /// Waits until the next record is due and returns it. The first record
+    /// is returned immediately and sets the start of the replay.
+    pub fn next_record(&mut self) -> Result<Option<Record>, CaptureError> {
+        let Some(record) = self.reader.read_record()? else {
+            return Ok(None);
+        };
+        let offset = match self.timing {
+            Timing::Original => record.time(),
+            // A tiny speed can push the offset past what a `Duration` holds.
+            Timing::Scaled(speed) => {
+                Duration::try_from_secs_f64(record.time().as_secs_f64() / speed)
+                    .unwrap_or(Duration::MAX)
+            }
+            Timing::AsFastAsPossible => return Ok(Some(record)),
+        };
+        let now = Instant::now();
+        let (start, first) = *self.start.get_or_insert((now, offset));
+        let due = start.checked_add(offset.saturating_sub(first));
+        if let Some(wait) = due.and_then(|due| due.checked_duration_since(now)) {
+            thread::sleep(wait);
+        }
+        Ok(Some(record))
+    }
+}
+
+impl<R: Read> Iterator for Replay<R> {
+    type Item = Result<Record, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ImpactReport, PacketData, mux::MuxMsgN};
+
+    fn impact(id: u8) -> Packet {
+        Packet {
+            data: PacketData::ImpactReport(ImpactReport { timestamp: 7 }),
+            id,
+        }
+    }
+
+    /// A capture of three records, and the length of the file up to the end
+    /// of each.
+    fn capture() -> (Vec<u8>, Vec<usize>) {
+        let header = Header {
+            version: Version::new([1, 2, 3]),
+            product_id: 5,
+            started_unix_us: 1_000,
+        };
+        let mut w = CaptureWriter::new(Vec::new(), &header).unwrap();
+        let mut ends = Vec::new();
+        let records = [
+            RecordBody::Packet(impact(1)),
+            RecordBody::Mux(MuxMsgN::RequestDevices),
+            RecordBody::Packet(impact(2)),
+        ];
+        for (i, body) in records.into_iter().enumerate() {
+            let time_us = 10 * i as u64;
+            w.write_record(&Record { time_us, body }).unwrap();
+            ends.push(w.inner.len());
+        }
+        (w.into_inner(), ends)
+    }
+
+    #[test]
+    fn records_read_back() {
+        let (file, _) = capture();
+        let r = CaptureReader::new(file.as_slice()).unwrap();
+        assert_eq!(r.header().version.firmware_semver, [1, 2, 3]);
+        assert_eq!(r.header().product_id, 5);
+        assert_eq!(r.header().started_unix_us, 1_000);
+
+        let records: Vec<Record> = r.map(Result::unwrap).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].time(), Duration::from_micros(20));
+        assert!(matches!(&records[0].body, RecordBody::Packet(p) if p.id == 1));
+        assert!(matches!(
+            records[1].body,
+            RecordBody::Mux(MuxMsgN::RequestDevices)
+        ));
+        assert!(matches!(&records[2].body, RecordBody::Packet(p) if p.id == 2));
+    }
+
+    #[test]
+    fn a_cut_short_capture_ends_at_its_last_complete_record() {
+        let (file, ends) = capture();
+        for cut in ends[0]..file.len() {
+            let r = CaptureReader::new(&file[..cut]).unwrap();
+            let complete = ends.iter().filter(|&&end| end <= cut).count();
+            let records: Vec<Record> = r.map(Result::unwrap).collect();
+            assert_eq!(records.len(), complete, "cut at {cut}");
+        }
+    }
+
+    #[test]
+    fn replay_rejects_invalid_speeds() {
+        let (file, _) = capture();
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let r = CaptureReader::new(file.as_slice()).unwrap();
+            assert!(matches!(
+                Replay::new(r, Timing::Scaled(speed)),
+                Err(CaptureError::InvalidSpeed(_))
+            ));
+        }
+
+        let r = CaptureReader::new(file.as_slice()).unwrap();
+        let replay = Replay::new(r, Timing::Scaled(1e6)).unwrap();
+        assert_eq!(replay.map(Result::unwrap).count(), 3);
+    }
+}
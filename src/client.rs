@@ -0,0 +1,381 @@
+//! Pairs requests with their responses by [`Packet::id`].
+//!
+//! [`Client`] itself does no I/O: it hands out packets to send and is fed the
+//! packets that come back. [`Client::call`] drives one request to completion
+//! over anything implementing [`Transport`].
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    time::{Duration, Instant},
+    vec::Vec,
+};
+
+use crate::{
+    Capabilities, PROTOCOL_SEMVER, Packet, PacketData, PacketType,
+    handshake::{self, Negotiated},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub enum ClientError {
+    /// Every id is taken by an outstanding request.
+    IdsExhausted,
+    /// No response arrived for request `id` within the client's timeout.
+    Timeout { id: u8, expected: PacketType },
+    /// Request `id` was answered with the wrong packet type.
+    UnexpectedResponse {
+        id: u8,
+        expected: PacketType,
+        actual: PacketType,
+    },
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ClientError as S;
+        match self {
+            S::IdsExhausted => write!(f, "no free packet ids"),
+            S::Timeout { id, expected } => {
+                write!(f, "request {id} timed out waiting for {expected:?}")
+            }
+            S::UnexpectedResponse {
+                id,
+                expected,
+                actual,
+            } => write!(f, "request {id} expected {expected:?}, got {actual:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+/// A packet fed to [`Client::handle`].
+#[derive(Clone, Debug)]
+pub enum Incoming {
+    /// The response to the outstanding request with the same id.
+    Response(Packet),
+    /// A response, by [`PacketType::is_response`], with the id of an
+    /// outstanding request that expected another type. The request is failed
+    /// and forgotten.
+    Mismatched { pkt: Packet, expected: PacketType },
+    /// A packet that answers no outstanding request, such as a stream report,
+    /// even if it shares an outstanding request's id.
+    Unsolicited(Packet),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Pending {
+    expected: PacketType,
+    sent: Instant,
+}
+
+#[derive(Clone, Debug)]
+pub struct Client {
+    next_id: u8,
+    timeout: Duration,
+    pending: HashMap<u8, Pending>,
+}
+
+impl Client {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            next_id: 0,
+            timeout,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Number of requests still waiting for a response.
+    pub fn outstanding(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Wraps `data` in a packet with a fresh id. If `data` expects a response,
+    /// the id stays reserved until [`Client::handle`] sees the response, the
+    /// request times out or it is cancelled.
+    pub fn request(&mut self, data: PacketData, now: Instant) -> Result<Packet, ClientError> {
+        if self.pending.len() > usize::from(u8::MAX) {
+            return Err(ClientError::IdsExhausted);
+        }
+        while self.pending.contains_key(&self.next_id) {
+            self.next_id = self.next_id.wrapping_add(1);
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        if let Some(expected) = data.expected_response() {
+            self.pending.insert(
+                id,
+                Pending {
+                    expected,
+                    sent: now,
+                },
+            );
+        }
+        Ok(Packet { data, id })
+    }
+
+    /// Stops waiting for the response to request `id`.
+    pub fn cancel(&mut self, id: u8) {
+        self.pending.remove(&id);
+    }
+
+    /// Matches `pkt` against the outstanding requests. A packet with the id
+    /// and the expected type of an outstanding request answers it, and a
+    /// response of another type with that id fails it. Any other packet is
+    /// unsolicited and leaves the requests waiting.
+    pub fn handle(&mut self, pkt: Packet) -> Incoming {
+        let Some(&Pending { expected, .. }) = self.pending.get(&pkt.id) else {
+            return Incoming::Unsolicited(pkt);
+        };
+        let ty = pkt.ty();
+        if u8::from(expected) == u8::from(ty) {
+            self.pending.remove(&pkt.id);
+            Incoming::Response(pkt)
+        } else if ty.is_response() {
+            self.pending.remove(&pkt.id);
+            Incoming::Mismatched { pkt, expected }
+        } else {
+            Incoming::Unsolicited(pkt)
+        }
+    }
+
+    /// Fails and forgets every request sent more than the client's timeout
+    /// before `now`.
+    pub fn expire(&mut self, now: Instant) -> Vec<ClientError> {
+        let timeout = self.timeout;
+        let mut expired = Vec::new();
+        self.pending.retain(|&id, p| {
+            let live = now.saturating_duration_since(p.sent) <= timeout;
+            if !live {
+                expired.push(ClientError::Timeout {
+                    id,
+                    expected: p.expected,
+                });
+            }
+            live
+        });
+        expired
+    }
+
+    /// Sends `data` over `transport` and waits for its response. A response of
+    /// the wrong type fails with [`ClientError::UnexpectedResponse`]. Packets
+    /// that arrive meanwhile and answer nothing, or answer another outstanding
+    /// request, are passed to `unsolicited`, which is then the only place the
+    /// other request's response shows up.
+    ///
+    /// Requests that expect no response return the sent packet once it is sent.
+    pub fn call<T: Transport>(
+        &mut self,
+        transport: &mut T,
+        data: PacketData,
+        mut unsolicited: impl FnMut(Packet),
+    ) -> Result<Packet, CallError<T::Error>> {
+        let sent = Instant::now();
+        let pkt = self.request(data, sent)?;
+        let id = pkt.id;
+        if let Err(e) = transport.send(&pkt) {
+            self.cancel(id);
+            return Err(CallError::Transport(e));
+        }
+        let Some(expected) = pkt.data.expected_response() else {
+            return Ok(pkt);
+        };
+        let deadline = sent + self.timeout;
+        loop {
+            let received = match transport.recv(deadline) {
+                Ok(Some(received)) => received,
+                Ok(None) => {
+                    self.cancel(id);
+                    return Err(ClientError::Timeout { id, expected }.into());
+                }
+                Err(e) => {
+                    self.cancel(id);
+                    return Err(CallError::Transport(e));
+                }
+            };
+            match self.handle(received) {
+                Incoming::Response(resp) if resp.id == id => return Ok(resp),
+                Incoming::Mismatched { pkt, expected } if pkt.id == id => {
+                    return Err(ClientError::UnexpectedResponse {
+                        id,
+                        expected,
+                        actual: pkt.ty(),
+                    }
+                    .into());
+                }
+                Incoming::Response(resp)
+                | Incoming::Mismatched { pkt: resp, .. }
+                | Incoming::Unsolicited(resp) => unsolicited(resp),
+            }
+        }
+    }
+}
+
+impl Client {
+    /// Reads the device's version over `transport` and, if it is compatible,
+    /// its capabilities, and negotiates with [`handshake::negotiate`]. An
+    /// incompatible device fails with
+    /// [`Error::VersionMismatch`](crate::Error::VersionMismatch) before it is
+    /// asked for anything else.
+    pub fn handshake<T: Transport>(
+        &mut self,
+        transport: &mut T,
+        mut unsolicited: impl FnMut(Packet),
+    ) -> Result<Negotiated, CallError<T::Error>> {
+        let resp = self.call(transport, PacketData::ReadVersion(), &mut unsolicited)?;
+        let PacketData::ReadVersionResponse(peer) = resp.data else {
+            return Err(ClientError::UnexpectedResponse {
+                id: resp.id,
+                expected: PacketType::ReadVersionResponse(),
+                actual: resp.ty(),
+            }
+            .into());
+        };
+        handshake::agree(PROTOCOL_SEMVER, peer.protocol_semver).map_err(CallError::Protocol)?;
+        let features = self.read_capabilities(transport, unsolicited)?;
+        handshake::negotiate(peer, features).map_err(CallError::Protocol)
+    }
+
+    /// Asks the device what it supports over `transport`.
+    pub fn read_capabilities<T: Transport>(
+        &mut self,
+        transport: &mut T,
+        unsolicited: impl FnMut(Packet),
+    ) -> Result<Capabilities, CallError<T::Error>> {
+        let resp = self.call(transport, PacketData::ReadCapabilities(), unsolicited)?;
+        match resp.data {
+            PacketData::ReadCapabilitiesResponse(caps) => Ok(caps),
+            _ => Err(ClientError::UnexpectedResponse {
+                id: resp.id,
+                expected: PacketType::ReadCapabilitiesResponse(),
+                actual: resp.ty(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// A packet link [`Client::call`] can drive.
+pub trait Transport {
+    type Error;
+    fn send(&mut self, pkt: &Packet) -> Result<(), Self::Error>;
+    /// Waits for the next packet until `deadline`, returning `Ok(None)` if none
+    /// arrived.
+    fn recv(&mut self, deadline: Instant) -> Result<Option<Packet>, Self::Error>;
+}
+
+#[derive(Debug)]
+pub enum CallError<E> {
+    Client(ClientError),
+    Transport(E),
+    /// The response arrived but its contents were rejected.
+    Protocol(crate::Error),
+}
+
+impl<E> From<ClientError> for CallError<E> {
+    fn from(e: ClientError) -> Self {
+        Self::Client(e)
+    }
+}
+
+impl<E: Display> Display for CallError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Client(e) => write!(f, "{e}"),
+            Self::Transport(e) => write!(f, "transport error: {e}"),
+            Self::Protocol(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for CallError<E> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ImpactReport, PropKind, Props};
+
+    #[test]
+    fn only_the_expected_type_answers_a_request() {
+        let mut client = Client::new(Duration::from_millis(100));
+        let now = Instant::now();
+        let req = client
+            .request(PacketData::ReadProp(PropKind::Uuid), now)
+            .unwrap();
+
+        let report = PacketData::ImpactReport(ImpactReport { timestamp: 1 });
+        let pkt = Packet {
+            data: report,
+            id: req.id,
+        };
+        assert!(matches!(client.handle(pkt), Incoming::Unsolicited(_)));
+        assert_eq!(client.outstanding(), 1);
+
+        let pkt = Packet {
+            data: PacketData::ReadPropResponse(Props::Uuid([1; 6])),
+            id: req.id,
+        };
+        assert!(matches!(client.handle(pkt), Incoming::Response(_)));
+        assert_eq!(client.outstanding(), 0);
+    }
+
+    #[test]
+    fn a_response_of_another_type_fails_the_request() {
+        let mut client = Client::new(Duration::from_millis(100));
+        let req = client
+            .request(PacketData::ReadProp(PropKind::Uuid), Instant::now())
+            .unwrap();
+        let pkt = Packet {
+            data: PacketData::Ack(),
+            id: req.id,
+        };
+        let incoming = client.handle(pkt);
+        assert!(matches!(
+            incoming,
+            Incoming::Mismatched {
+                expected: PacketType::ReadPropResponse(),
+                ..
+            }
+        ));
+        assert_eq!(client.outstanding(), 0);
+    }
+
+    /// Answers every request with `reply`, under the request's id.
+    struct Echo {
+        reply: PacketData,
+        queue: Vec<Packet>,
+    }
+
+    impl Transport for Echo {
+        type Error = ();
+        fn send(&mut self, pkt: &Packet) -> Result<(), ()> {
+            self.queue.push(Packet {
+                data: self.reply.clone(),
+                id: pkt.id,
+            });
+            Ok(())
+        }
+        fn recv(&mut self, _: Instant) -> Result<Option<Packet>, ()> {
+            Ok(self.queue.pop())
+        }
+    }
+
+    #[test]
+    fn call_reports_an_unexpected_response() {
+        let mut client = Client::new(Duration::from_millis(100));
+        let mut echo = Echo {
+            reply: PacketData::Ack(),
+            queue: Vec::new(),
+        };
+        let r = client.call(&mut echo, PacketData::ReadVersion(), |_| {});
+        assert!(matches!(
+            r,
+            Err(CallError::Client(ClientError::UnexpectedResponse {
+                expected: PacketType::ReadVersionResponse(),
+                actual: PacketType::Ack(),
+                ..
+            }))
+        ));
+        assert_eq!(client.outstanding(), 0);
+    }
+}
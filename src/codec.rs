@@ -0,0 +1,171 @@
+//! [`tokio_util::codec`] support, for wrapping a serial port or a socket in a
+//! `Framed` stream.
+//!
+//! Every message travels as one [`framing`] frame. [`Packet`]s use the binary
+//! encoding of [`Packet::serialize`]; mux and control messages use their
+//! minicbor encoding.
+
+use core::marker::PhantomData;
+use std::{io, vec::Vec};
+
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{
+    Packet,
+    control::{
+        device::DeviceMsg,
+        usb_mux::{UsbMuxCtrlMsg, UsbMuxCtrlMsgN},
+    },
+    framing,
+    mux::{MuxMsg, MuxMsgN},
+};
+
+/// Largest encoded frame accepted for a CBOR message.
+pub const MAX_CBOR_FRAME_LEN: usize = 4096;
+
+/// A message a [`Codec`] can carry.
+pub trait Message: Sized {
+    /// Largest encoded frame, without the delimiter, the decoder buffers before
+    /// giving up on it.
+    const MAX_FRAME_LEN: usize;
+    fn encode(&self, buf: &mut Vec<u8>) -> io::Result<()>;
+    /// Returns `None` if `bytes` does not hold a valid message.
+    fn decode(bytes: &[u8]) -> Option<Self>;
+}
+
+impl Message for Packet {
+    const MAX_FRAME_LEN: usize = framing::MAX_PACKET_FRAME_LEN;
+
+    fn encode(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+        self.serialize_to_vec(buf);
+        Ok(())
+    }
+
+    fn decode(mut bytes: &[u8]) -> Option<Self> {
+        Packet::parse(&mut bytes).ok()
+    }
+}
+
+fn encode_cbor<T: minicbor::Encode<()>>(v: &T, buf: &mut Vec<u8>) -> io::Result<()> {
+    crate::vec_cbor::encode(v, buf)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "cbor encode error"))
+}
+
+fn decode_cbor<'b, T: minicbor::Decode<'b, ()>>(bytes: &'b [u8]) -> Option<T> {
+    minicbor::decode(bytes).ok()
+}
+
+macro_rules! cbor_message {
+    ($([$($generics:tt)*] $ty:ty),*) => {
+        $(
+            impl<$($generics)*> Message for $ty {
+                const MAX_FRAME_LEN: usize = MAX_CBOR_FRAME_LEN;
+
+                fn encode(&self, buf: &mut Vec<u8>) -> io::Result<()> {
+                    encode_cbor(self, buf)
+                }
+
+                fn decode(bytes: &[u8]) -> Option<Self> {
+                    decode_cbor(bytes)
+                }
+            }
+        )*
+    };
+}
+
+cbor_message!(
+    [const N: usize] MuxMsgN<N>,
+    [const N: usize] UsbMuxCtrlMsgN<N>,
+    [] DeviceMsg
+);
+
+/// Frames `T`s on a byte stream.
+///
+/// A frame that is not valid COBS, does not decode or grows past
+/// [`Message::MAX_FRAME_LEN`] is dropped and counted, and decoding resumes at
+/// the next frame, so one corrupted frame does not end the stream. Only I/O
+/// errors are returned.
+#[derive(Debug)]
+pub struct Codec<T> {
+    /// Discarding bytes up to the next delimiter after an overlong frame.
+    skipping: bool,
+    dropped: usize,
+    _msg: PhantomData<fn() -> T>,
+}
+
+pub type PacketCodec = Codec<Packet>;
+pub type MuxCodec = Codec<MuxMsg>;
+pub type UsbMuxCtrlCodec = Codec<UsbMuxCtrlMsg>;
+pub type DeviceCodec = Codec<DeviceMsg>;
+
+impl<T> Codec<T> {
+    pub fn new() -> Self {
+        Self {
+            skipping: false,
+            dropped: 0,
+            _msg: PhantomData,
+        }
+    }
+
+    /// Number of frames dropped so far.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+impl<T> Default for Codec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Message> Decoder for Codec<T> {
+    type Item = T;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<T>> {
+        while let Some(end) = src.iter().position(|&b| b == 0) {
+            let mut frame = src.split_to(end + 1);
+            frame.truncate(end);
+            if core::mem::take(&mut self.skipping) || frame.is_empty() {
+                continue;
+            }
+            let len = framing::decode_in_place(&mut frame).ok();
+            if let Some(msg) = len.and_then(|len| T::decode(&frame[..len])) {
+                return Ok(Some(msg));
+            }
+            self.dropped += 1;
+        }
+        if src.len() > T::MAX_FRAME_LEN {
+            src.clear();
+            if !self.skipping {
+                self.skipping = true;
+                self.dropped += 1;
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<T: Message> Encoder<&T> for Codec<T> {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: &T, dst: &mut BytesMut) -> io::Result<()> {
+        let mut buf = Vec::new();
+        item.encode(&mut buf)?;
+        let start = dst.len();
+        dst.resize(start + framing::max_encoded_len(buf.len()), 0);
+        let len = framing::encode(&buf, &mut dst[start..]).expect("dst sized for the frame");
+        dst.truncate(start + len);
+        Ok(())
+    }
+}
+
+impl<T: Message> Encoder<T> for Codec<T> {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> io::Result<()> {
+        self.encode(&item, dst)
+    }
+}
@@ -1,10 +1,17 @@
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
 #[derive(Debug, Clone, Copy)]
 pub enum PairingError {
+    #[cfg_attr(feature = "minicbor", n(0))]
     Timeout,
+    #[cfg_attr(feature = "minicbor", n(1))]
     Cancelled,
+    #[cfg_attr(feature = "minicbor", n(2))]
     NotBleMode,
 }
 
@@ -26,9 +33,14 @@ pub enum TransportMode {
 
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
 #[derive(Clone, Copy, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ClearBondsError {
+    #[cfg_attr(feature = "minicbor", n(0))]
     Failed,
 }
 
@@ -36,44 +48,87 @@ pub enum ClearBondsError {
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
 #[derive(Clone, Copy, Debug)]
 pub struct StartPairing {
+    #[cfg_attr(feature = "minicbor", n(0))]
     pub timeout_ms: u32,
 }
 
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
 #[derive(Clone, Debug)]
 pub enum DeviceMsg {
-    ReadProp(crate::PropKind),
-    ReadPropResponse(crate::Props),
+    #[cfg_attr(feature = "minicbor", n(0))]
+    ReadProp(#[cfg_attr(feature = "minicbor", n(0))] crate::PropKind),
+    #[cfg_attr(feature = "minicbor", n(1))]
+    ReadPropResponse(#[cfg_attr(feature = "minicbor", n(0))] crate::Props),
 
+    #[cfg_attr(feature = "minicbor", n(2))]
     ClearBond,
-    ClearBondResponse(Result<(), ClearBondsError>),
+    #[cfg_attr(feature = "minicbor", n(3))]
+    ClearBondResponse(#[cfg_attr(feature = "minicbor", n(0))] Result<(), ClearBondsError>),
 
+    #[cfg_attr(feature = "minicbor", n(4))]
     GetTransportMode,
-    SetTransportMode(TransportMode),
-    TransportModeStatus(TransportMode),
+    #[cfg_attr(feature = "minicbor", n(5))]
+    SetTransportMode(#[cfg_attr(feature = "minicbor", n(0))] TransportMode),
+    #[cfg_attr(feature = "minicbor", n(6))]
+    TransportModeStatus(#[cfg_attr(feature = "minicbor", n(0))] TransportMode),
 
-    StartPairing(StartPairing),
+    #[cfg_attr(feature = "minicbor", n(7))]
+    StartPairing(#[cfg_attr(feature = "minicbor", n(0))] StartPairing),
+    #[cfg_attr(feature = "minicbor", n(8))]
     StartPairingResponse,
+    #[cfg_attr(feature = "minicbor", n(9))]
     CancelPairing,
-    PairingResult(Result<super::BondedDevice, PairingError>),
+    #[cfg_attr(feature = "minicbor", n(10))]
+    PairingResult(
+        #[cfg_attr(feature = "minicbor", n(0))] Result<super::BondedDevice, PairingError>,
+    ),
 
-    ReadConfig(crate::ConfigKind),
-    ReadConfigResponse(crate::GeneralConfig),
-    WriteConfig(crate::GeneralConfig),
+    #[cfg_attr(feature = "minicbor", n(11))]
+    ReadConfig(#[cfg_attr(feature = "minicbor", n(0))] crate::ConfigKind),
+    #[cfg_attr(feature = "minicbor", n(12))]
+    ReadConfigResponse(#[cfg_attr(feature = "minicbor", n(0))] crate::GeneralConfig),
+    #[cfg_attr(feature = "minicbor", n(13))]
+    WriteConfig(#[cfg_attr(feature = "minicbor", n(0))] crate::GeneralConfig),
+    #[cfg_attr(feature = "minicbor", n(14))]
     WriteConfigAck,
+    #[cfg_attr(feature = "minicbor", n(15))]
     FlashSettings,
+    #[cfg_attr(feature = "minicbor", n(16))]
     FlashSettingsAck,
 
+    #[cfg_attr(feature = "minicbor", n(17))]
     Reboot,
+    #[cfg_attr(feature = "minicbor", n(18))]
     RebootAck,
 
-    AddBond(super::BondEntry),
-    AddBondResponse(Result<(), super::AddBondError>),
+    #[cfg_attr(feature = "minicbor", n(19))]
+    AddBond(#[cfg_attr(feature = "minicbor", n(0))] super::BondEntry),
+    #[cfg_attr(feature = "minicbor", n(20))]
+    AddBondResponse(#[cfg_attr(feature = "minicbor", n(0))] Result<(), super::AddBondError>),
+
+    #[cfg_attr(feature = "minicbor", n(21))]
+    SetDeviceName(
+        #[cfg_attr(feature = "minicbor", n(0))]
+        #[cfg_attr(feature = "minicbor", cbor(with = "crate::heapless_str32_cbor"))]
+        heapless::String<32>,
+    ),
+    #[cfg_attr(feature = "minicbor", n(22))]
+    SetDeviceNameResponse(#[cfg_attr(feature = "minicbor", n(0))] Result<(), ()>),
 
-    SetDeviceName(heapless::String<32>),
-    SetDeviceNameResponse(Result<(), ()>),
+    #[cfg_attr(feature = "minicbor", n(23))]
+    ReadCapabilities,
+    #[cfg_attr(feature = "minicbor", n(24))]
+    ReadCapabilitiesResponse(#[cfg_attr(feature = "minicbor", n(0))] crate::Capabilities),
 }
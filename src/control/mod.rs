@@ -22,18 +22,25 @@ pub struct BondEntry {
 
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "minicbor", derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen))]
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct BondedDevice {
+    #[cfg_attr(feature = "minicbor", n(0))]
     pub uuid: [u8; 6],
+    #[cfg_attr(feature = "minicbor", n(1))]
+    #[cfg_attr(feature = "minicbor", cbor(with = "crate::heapless_str32_cbor"))]
     pub name: heapless::String<32>,
 }
 
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "minicbor", derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen))]
 #[derive(Clone, Copy, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum AddBondError {
+    #[cfg_attr(feature = "minicbor", n(0))]
     Full,
+    #[cfg_attr(feature = "minicbor", n(1))]
     Failed,
 }
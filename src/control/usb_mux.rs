@@ -1,55 +1,290 @@
+use crate::mux::MAX_DEVICES;
+
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
 #[derive(Debug, Clone, Copy)]
 pub enum PairingError {
+    #[cfg_attr(feature = "minicbor", n(0))]
     Timeout,
+    #[cfg_attr(feature = "minicbor", n(1))]
     Cancelled,
 }
 
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
 #[derive(Clone, Copy, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ClearBondsError {
+    #[cfg_attr(feature = "minicbor", n(0))]
     Failed,
 }
 
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
 #[derive(Clone, Copy, Debug)]
 pub enum BondStoreError {
+    #[cfg_attr(feature = "minicbor", n(0))]
     Full,
 }
 
 #[repr(C)]
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
 #[derive(Clone, Copy, Debug)]
 pub struct StartPairing {
+    #[cfg_attr(feature = "minicbor", n(0))]
     pub timeout_ms: u32,
 }
 
-#[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
+/// [`UsbMuxCtrlMsgN`] with the default capacity of [`MAX_DEVICES`].
+pub type UsbMuxCtrlMsg = UsbMuxCtrlMsgN<MAX_DEVICES>;
+
+/// The Python class for [`UsbMuxCtrlMsg`]. pyo3 classes cannot be generic,
+/// so Python only sees the default capacity.
+#[cfg(feature = "pyo3")]
+#[pyo3::pyclass(name = "UsbMuxCtrlMsg")]
+#[derive(Clone, Debug)]
+pub struct PyUsbMuxCtrlMsg(pub UsbMuxCtrlMsg);
+
+#[cfg(feature = "pyo3")]
+impl From<UsbMuxCtrlMsg> for PyUsbMuxCtrlMsg {
+    fn from(msg: UsbMuxCtrlMsg) -> Self {
+        Self(msg)
+    }
+}
+
+#[cfg(feature = "pyo3")]
+impl From<PyUsbMuxCtrlMsg> for UsbMuxCtrlMsg {
+    fn from(msg: PyUsbMuxCtrlMsg) -> Self {
+        msg.0
+    }
+}
+
+/// Python builds the messages a host sends with the static constructors and
+/// reads a message through `variant` and the field getters, which are `None`
+/// for variants without that field.
+#[cfg(feature = "pyo3")]
+#[pyo3::pymethods]
+impl PyUsbMuxCtrlMsg {
+    #[staticmethod]
+    fn read_version() -> Self {
+        Self(UsbMuxCtrlMsg::ReadVersion())
+    }
+    #[staticmethod]
+    fn list_bonds() -> Self {
+        Self(UsbMuxCtrlMsg::ListBonds)
+    }
+    #[staticmethod]
+    fn clear_bonds() -> Self {
+        Self(UsbMuxCtrlMsg::ClearBonds)
+    }
+    #[staticmethod]
+    fn start_pairing(timeout_ms: u32) -> Self {
+        Self(UsbMuxCtrlMsg::StartPairing(StartPairing { timeout_ms }))
+    }
+    #[staticmethod]
+    fn cancel_pairing() -> Self {
+        Self(UsbMuxCtrlMsg::CancelPairing)
+    }
+    #[staticmethod]
+    fn add_bond(entry: super::BondEntry) -> Self {
+        Self(UsbMuxCtrlMsg::AddBond(entry))
+    }
+    #[staticmethod]
+    fn update_bond_name(uuid: crate::mux::Uuid, name: &str) -> pyo3::PyResult<Self> {
+        let name = name
+            .try_into()
+            .map_err(|_| pyo3::exceptions::PyValueError::new_err("name is over 32 bytes"))?;
+        Ok(Self(UsbMuxCtrlMsg::UpdateBondName { uuid, name }))
+    }
+    #[staticmethod]
+    fn read_capabilities() -> Self {
+        Self(UsbMuxCtrlMsg::ReadCapabilities)
+    }
+
+    /// The variant name, e.g. `"PairingResult"`.
+    #[getter]
+    fn variant(&self) -> &'static str {
+        match &self.0 {
+            UsbMuxCtrlMsg::ReadVersion() => "ReadVersion",
+            UsbMuxCtrlMsg::ReadVersionResponse(_) => "ReadVersionResponse",
+            UsbMuxCtrlMsg::ListBonds => "ListBonds",
+            UsbMuxCtrlMsg::ListBondsResponse(_) => "ListBondsResponse",
+            UsbMuxCtrlMsg::ClearBonds => "ClearBonds",
+            UsbMuxCtrlMsg::ClearBondsResponse(_) => "ClearBondsResponse",
+            UsbMuxCtrlMsg::BondStoreError(_) => "BondStoreError",
+            UsbMuxCtrlMsg::StartPairing(_) => "StartPairing",
+            UsbMuxCtrlMsg::StartPairingResponse => "StartPairingResponse",
+            UsbMuxCtrlMsg::CancelPairing => "CancelPairing",
+            UsbMuxCtrlMsg::PairingResult(_) => "PairingResult",
+            UsbMuxCtrlMsg::AddBond(_) => "AddBond",
+            UsbMuxCtrlMsg::AddBondResponse(_) => "AddBondResponse",
+            UsbMuxCtrlMsg::UpdateBondName { .. } => "UpdateBondName",
+            UsbMuxCtrlMsg::ReadCapabilities => "ReadCapabilities",
+            UsbMuxCtrlMsg::ReadCapabilitiesResponse(_) => "ReadCapabilitiesResponse",
+        }
+    }
+    #[getter]
+    fn version(&self) -> Option<crate::Version> {
+        match &self.0 {
+            UsbMuxCtrlMsg::ReadVersionResponse(v) => Some(*v),
+            _ => None,
+        }
+    }
+    /// The bonds of a `ListBondsResponse`.
+    #[getter]
+    fn bonds(&self) -> Option<std::vec::Vec<super::BondedDevice>> {
+        match &self.0 {
+            UsbMuxCtrlMsg::ListBondsResponse(bonds) => Some(bonds.to_vec()),
+            _ => None,
+        }
+    }
+    /// The device bonded by a successful `PairingResult`.
+    #[getter]
+    fn bonded(&self) -> Option<super::BondedDevice> {
+        match &self.0 {
+            UsbMuxCtrlMsg::PairingResult(Ok(dev)) => Some(dev.clone()),
+            _ => None,
+        }
+    }
+    #[getter]
+    fn entry(&self) -> Option<super::BondEntry> {
+        match &self.0 {
+            UsbMuxCtrlMsg::AddBond(entry) => Some(*entry),
+            _ => None,
+        }
+    }
+    #[getter]
+    fn timeout_ms(&self) -> Option<u32> {
+        match &self.0 {
+            UsbMuxCtrlMsg::StartPairing(p) => Some(p.timeout_ms),
+            _ => None,
+        }
+    }
+    #[getter]
+    fn uuid(&self) -> Option<crate::mux::Uuid> {
+        match &self.0 {
+            UsbMuxCtrlMsg::UpdateBondName { uuid, .. } => Some(*uuid),
+            _ => None,
+        }
+    }
+    #[getter]
+    fn name(&self) -> Option<std::string::String> {
+        match &self.0 {
+            UsbMuxCtrlMsg::UpdateBondName { name, .. } => Some(name.as_str().into()),
+            _ => None,
+        }
+    }
+    #[getter]
+    fn capabilities(&self) -> Option<crate::Capabilities> {
+        match &self.0 {
+            UsbMuxCtrlMsg::ReadCapabilitiesResponse(c) => Some(*c),
+            _ => None,
+        }
+    }
+    /// Whether a response carrying a result succeeded.
+    #[getter]
+    fn ok(&self) -> Option<bool> {
+        match &self.0 {
+            UsbMuxCtrlMsg::ClearBondsResponse(r) => Some(r.is_ok()),
+            UsbMuxCtrlMsg::PairingResult(r) => Some(r.is_ok()),
+            UsbMuxCtrlMsg::AddBondResponse(r) => Some(r.is_ok()),
+            UsbMuxCtrlMsg::BondStoreError(_) => Some(false),
+            _ => None,
+        }
+    }
+    /// The error of a failed response, e.g. `"Timeout"` for a `PairingResult`.
+    #[getter]
+    fn error(&self) -> Option<std::string::String> {
+        match &self.0 {
+            UsbMuxCtrlMsg::ClearBondsResponse(Err(e)) => Some(std::format!("{e:?}")),
+            UsbMuxCtrlMsg::PairingResult(Err(e)) => Some(std::format!("{e:?}")),
+            UsbMuxCtrlMsg::AddBondResponse(Err(e)) => Some(std::format!("{e:?}")),
+            UsbMuxCtrlMsg::BondStoreError(e) => Some(std::format!("{e:?}")),
+            _ => None,
+        }
+    }
+
+    fn __repr__(&self) -> std::string::String {
+        std::format!("{:?}", self.0)
+    }
+}
+
+/// A control message for a dongle that stores up to `N` bonds.
+///
+/// Decoding a `ListBondsResponse` with more than `N` bonds fails rather than
+/// dropping the extra bonds.
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
 #[derive(Clone, Debug)]
-pub enum UsbMuxCtrlMsg {
+pub enum UsbMuxCtrlMsgN<const N: usize> {
+    #[cfg_attr(feature = "minicbor", n(0))]
     ReadVersion(),
-    ReadVersionResponse(crate::Version),
+    #[cfg_attr(feature = "minicbor", n(1))]
+    ReadVersionResponse(#[cfg_attr(feature = "minicbor", n(0))] crate::Version),
 
+    #[cfg_attr(feature = "minicbor", n(2))]
     ListBonds,
-    ListBondsResponse(heapless::Vec<super::BondedDevice, { crate::mux::MAX_DEVICES }>),
+    #[cfg_attr(feature = "minicbor", n(3))]
+    ListBondsResponse(
+        #[cfg_attr(feature = "minicbor", n(0))]
+        #[cfg_attr(feature = "minicbor", cbor(with = "crate::heapless_vec_cbor"))]
+        heapless::Vec<super::BondedDevice, N>,
+    ),
 
+    #[cfg_attr(feature = "minicbor", n(4))]
     ClearBonds,
-    ClearBondsResponse(Result<(), ClearBondsError>),
-    BondStoreError(BondStoreError),
+    #[cfg_attr(feature = "minicbor", n(5))]
+    ClearBondsResponse(#[cfg_attr(feature = "minicbor", n(0))] Result<(), ClearBondsError>),
+    #[cfg_attr(feature = "minicbor", n(6))]
+    BondStoreError(#[cfg_attr(feature = "minicbor", n(0))] BondStoreError),
 
-    StartPairing(StartPairing),
+    #[cfg_attr(feature = "minicbor", n(7))]
+    StartPairing(#[cfg_attr(feature = "minicbor", n(0))] StartPairing),
+    #[cfg_attr(feature = "minicbor", n(8))]
     StartPairingResponse,
+    #[cfg_attr(feature = "minicbor", n(9))]
     CancelPairing,
-    PairingResult(Result<super::BondedDevice, PairingError>),
+    #[cfg_attr(feature = "minicbor", n(10))]
+    PairingResult(
+        #[cfg_attr(feature = "minicbor", n(0))] Result<super::BondedDevice, PairingError>,
+    ),
+
+    #[cfg_attr(feature = "minicbor", n(11))]
+    AddBond(#[cfg_attr(feature = "minicbor", n(0))] super::BondEntry),
+    #[cfg_attr(feature = "minicbor", n(12))]
+    AddBondResponse(#[cfg_attr(feature = "minicbor", n(0))] Result<(), super::AddBondError>),
 
-    AddBond(super::BondEntry),
-    AddBondResponse(Result<(), super::AddBondError>),
+    #[cfg_attr(feature = "minicbor", n(13))]
+    UpdateBondName {
+        #[cfg_attr(feature = "minicbor", n(0))]
+        uuid: crate::mux::Uuid,
+        #[cfg_attr(feature = "minicbor", n(1))]
+        #[cfg_attr(feature = "minicbor", cbor(with = "crate::heapless_str32_cbor"))]
+        name: heapless::String<32>,
+    },
 
-    UpdateBondName { uuid: crate::mux::Uuid, name: heapless::String<32> },
+    #[cfg_attr(feature = "minicbor", n(14))]
+    ReadCapabilities,
+    #[cfg_attr(feature = "minicbor", n(15))]
+    ReadCapabilitiesResponse(#[cfg_attr(feature = "minicbor", n(0))] crate::Capabilities),
 }
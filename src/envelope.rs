@@ -0,0 +1,85 @@
+//! Optional integrity check around an encoded message.
+//!
+//! The envelope is the encoded bytes of a [`Packet`], a CBOR-encoded
+//! [`MuxMsg`](crate::mux::MuxMsg) or anything else, followed by a little-endian
+//! CRC over them. [`open`] verifies and strips it, so a corrupted frame surfaces
+//! as [`Error::ChecksumMismatch`] rather than as a misparsed message.
+
+use core::mem::MaybeUninit;
+
+use crate::{Error, Packet, push};
+
+pub trait Checksum {
+    /// Bytes the checksum takes on the wire.
+    const LEN: usize;
+    fn compute(data: &[u8]) -> u32;
+}
+
+/// CRC-16/IBM-3740, also known as CRC-16/CCITT-FALSE.
+pub struct Crc16;
+
+impl Checksum for Crc16 {
+    const LEN: usize = 2;
+    fn compute(data: &[u8]) -> u32 {
+        const CRC: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_IBM_3740);
+        CRC.checksum(data).into()
+    }
+}
+
+/// CRC-32/ISO-HDLC, the common zlib/Ethernet CRC-32.
+pub struct Crc32;
+
+impl Checksum for Crc32 {
+    const LEN: usize = 4;
+    fn compute(data: &[u8]) -> u32 {
+        const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+        CRC.checksum(data)
+    }
+}
+
+/// Appends the checksum of `buf[..len]` after it and returns the sealed length.
+///
+/// Panics if `buf` is shorter than `len + C::LEN`.
+pub fn seal<C: Checksum>(buf: &mut [u8], len: usize) -> usize {
+    let crc = C::compute(&buf[..len]).to_le_bytes();
+    buf[len..len + C::LEN].copy_from_slice(&crc[..C::LEN]);
+    len + C::LEN
+}
+
+/// Verifies the trailing checksum of `frame` and returns the bytes it covers.
+///
+/// `expected` in [`Error::ChecksumMismatch`] is the checksum carried by the
+/// frame, `actual` the one computed over the received bytes.
+pub fn open<C: Checksum>(frame: &[u8]) -> Result<&[u8], Error> {
+    let Some(split) = frame.len().checked_sub(C::LEN) else {
+        return Err(Error::UnexpectedEof { packet_type: None });
+    };
+    let (data, crc) = frame.split_at(split);
+    let mut expected = [0; 4];
+    expected[..C::LEN].copy_from_slice(crc);
+    let expected = u32::from_le_bytes(expected);
+    let actual = C::compute(data);
+    if expected != actual {
+        return Err(Error::ChecksumMismatch { expected, actual });
+    }
+    Ok(data)
+}
+
+/// Serializes `pkt` with [`Packet::serialize`] and appends its checksum. Returns
+/// the number of bytes written, [`Packet::serialized_len`] plus `C::LEN`.
+///
+/// Panics if `buf` is too small.
+pub fn serialize_packet<C: Checksum>(pkt: &Packet, mut buf: &mut [MaybeUninit<u8>]) -> usize {
+    let len = pkt.serialize(buf);
+    let data = unsafe { core::slice::from_raw_parts(buf.as_ptr() as *const u8, len) };
+    let crc = C::compute(data).to_le_bytes();
+    buf = &mut buf[len..];
+    push(&mut buf, &crc[..C::LEN]);
+    len + C::LEN
+}
+
+/// Verifies the checksum on a frame written by [`serialize_packet`] and parses
+/// the packet inside it.
+pub fn parse_packet<C: Checksum>(frame: &[u8]) -> Result<Packet, Error> {
+    Packet::parse(&mut open::<C>(frame)?)
+}
@@ -0,0 +1,372 @@
+//! Fragmentation and reassembly of encoded messages for MTU-limited links such
+//! as BLE, where an `ObjectReport` does not fit in one ATT write.
+//!
+//! Every fragment starts with a [`HEADER_LEN`]-byte header:
+//! `[id, index, count, offset_le]`. `id` is the [`Packet::id`](crate::Packet::id)
+//! of the message, `index` and `count` number its fragments and `offset` is where
+//! the payload goes in the reassembled message, so fragments may arrive in any
+//! order.
+
+pub const HEADER_LEN: usize = 5;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FragmentError {
+    /// The MTU leaves no room for payload, or the message needs more than 255
+    /// fragments.
+    BadMtu,
+    /// The fragment is shorter than its header or its header is inconsistent.
+    Malformed,
+    /// The reassembled message would not fit in the reassembler's buffer.
+    TooLarge,
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for FragmentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use FragmentError as S;
+        match self {
+            S::BadMtu => write!(f, "mtu too small for message"),
+            S::Malformed => write!(f, "malformed fragment"),
+            S::TooLarge => write!(f, "reassembled message too large"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FragmentError {}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fragment<'a> {
+    pub id: u8,
+    pub index: u8,
+    pub count: u8,
+    pub offset: u16,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Fragment<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, FragmentError> {
+        let Some(([id, index, count, o0, o1], payload)) = bytes.split_first_chunk() else {
+            return Err(FragmentError::Malformed);
+        };
+        let fragment = Self {
+            id: *id,
+            index: *index,
+            count: *count,
+            offset: u16::from_le_bytes([*o0, *o1]),
+            payload,
+        };
+        fragment.chunk()?;
+        Ok(fragment)
+    }
+
+    /// The fragment size of the whole message, as implied by this fragment:
+    /// every fragment but the last carries exactly that many bytes and starts
+    /// at `index` times it. `None` for the only fragment of a message.
+    fn chunk(&self) -> Result<Option<usize>, FragmentError> {
+        let index = usize::from(self.index);
+        let offset = usize::from(self.offset);
+        let len = self.payload.len();
+        if self.index >= self.count {
+            Err(FragmentError::Malformed)
+        } else if self.index + 1 < self.count {
+            if len == 0 || offset != index * len {
+                return Err(FragmentError::Malformed);
+            }
+            Ok(Some(len))
+        } else {
+            // The last fragment may be short, and the only one has no offset.
+            match offset.checked_div(index) {
+                None if offset == 0 => Ok(None),
+                Some(chunk) if chunk > 0 && chunk * index == offset && len <= chunk => {
+                    Ok(Some(chunk))
+                }
+                _ => Err(FragmentError::Malformed),
+            }
+        }
+    }
+
+    /// Length of the fragment on the wire.
+    pub fn wire_len(&self) -> usize {
+        HEADER_LEN + self.payload.len()
+    }
+
+    /// Writes the header and payload to the front of `buf` and returns
+    /// [`Fragment::wire_len`].
+    ///
+    /// Panics if `buf` is shorter than that.
+    pub fn write(&self, buf: &mut [u8]) -> usize {
+        let [o0, o1] = self.offset.to_le_bytes();
+        buf[..HEADER_LEN].copy_from_slice(&[self.id, self.index, self.count, o0, o1]);
+        buf[HEADER_LEN..self.wire_len()].copy_from_slice(self.payload);
+        self.wire_len()
+    }
+}
+
+/// Splits `data`, the encoded message with packet id `id`, into fragments of
+/// at most `mtu` bytes each, header included.
+pub fn fragments(id: u8, data: &[u8], mtu: usize) -> Result<Fragments<'_>, FragmentError> {
+    let chunk = mtu.checked_sub(HEADER_LEN).filter(|c| *c > 0);
+    let chunk = chunk.ok_or(FragmentError::BadMtu)?;
+    let count = data.len().div_ceil(chunk).max(1);
+    if count > usize::from(u8::MAX) || data.len() > usize::from(u16::MAX) {
+        return Err(FragmentError::BadMtu);
+    }
+    Ok(Fragments {
+        id,
+        data,
+        chunk,
+        index: 0,
+        count: count as u8,
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct Fragments<'a> {
+    id: u8,
+    data: &'a [u8],
+    chunk: usize,
+    index: u8,
+    count: u8,
+}
+
+impl<'a> Iterator for Fragments<'a> {
+    type Item = Fragment<'a>;
+
+    fn next(&mut self) -> Option<Fragment<'a>> {
+        if self.index == self.count {
+            return None;
+        }
+        let offset = usize::from(self.index) * self.chunk;
+        let end = (offset + self.chunk).min(self.data.len());
+        let fragment = Fragment {
+            id: self.id,
+            index: self.index,
+            count: self.count,
+            offset: offset as u16,
+            payload: &self.data[offset..end],
+        };
+        self.index += 1;
+        Some(fragment)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Slot<const N: usize> {
+    id: u8,
+    count: u8,
+    /// Bit `i` is set once fragment `i` has been stored.
+    received: [u32; 8],
+    /// Set once the last fragment has been stored.
+    len: Option<usize>,
+    /// Fragment size, once a fragment other than a lone one has been stored.
+    chunk: Option<usize>,
+    /// When the first fragment arrived or, once `done`, the last one.
+    started: u64,
+    done: bool,
+    buf: [u8; N],
+}
+
+impl<const N: usize> Slot<N> {
+    fn complete(&self) -> bool {
+        let received: u32 = self.received.iter().map(|w| w.count_ones()).sum();
+        self.len.is_some() && received == u32::from(self.count)
+    }
+}
+
+/// Reassembles fragments into messages of at most `N` bytes, with up to `SLOTS`
+/// messages in flight at once, keyed by packet id.
+///
+/// Time is whatever monotonic unit the caller passes as `now`. A message whose
+/// first fragment arrived more than `timeout` before `now` is dropped, so a
+/// missing fragment only costs its own message. When every slot is busy, the
+/// oldest in-flight message is dropped to make room.
+///
+/// A completed message keeps its slot for another `timeout`, and fragments of
+/// it that arrive in that time, such as retransmissions, are ignored.
+#[derive(Clone, Debug)]
+pub struct Reassembler<const SLOTS: usize, const N: usize> {
+    slots: [Option<Slot<N>>; SLOTS],
+    timeout: u64,
+    dropped: usize,
+}
+
+impl<const SLOTS: usize, const N: usize> Reassembler<SLOTS, N> {
+    pub fn new(timeout: u64) -> Self {
+        Self {
+            slots: core::array::from_fn(|_| None),
+            timeout,
+            dropped: 0,
+        }
+    }
+
+    /// Number of incomplete messages dropped so far, by timeout or eviction.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Drops incomplete messages that have timed out, and forgets completed
+    /// ones once the same timeout has passed.
+    pub fn expire(&mut self, now: u64) {
+        for i in 0..SLOTS {
+            let timed_out = self.slots[i]
+                .as_ref()
+                .is_some_and(|s| now.saturating_sub(s.started) > self.timeout);
+            if timed_out {
+                self.evict(i);
+            }
+        }
+    }
+
+    fn evict(&mut self, i: usize) {
+        if self.slots[i].take().is_some_and(|s| !s.done) {
+            self.dropped += 1;
+        }
+    }
+
+    /// Stores `fragment` and, if it completes its message, returns the message.
+    /// A fragment whose `count` disagrees with the message under the same id
+    /// starts a new message. A fragment whose offset disagrees with its index
+    /// or with the other fragments of its message is rejected as
+    /// [`FragmentError::Malformed`].
+    pub fn push(
+        &mut self,
+        fragment: Fragment<'_>,
+        now: u64,
+    ) -> Result<Option<&[u8]>, FragmentError> {
+        let chunk = fragment.chunk()?;
+        let Fragment {
+            id,
+            index,
+            count,
+            offset,
+            payload,
+        } = fragment;
+        let offset = usize::from(offset);
+        let end = offset + payload.len();
+        if end > N {
+            return Err(FragmentError::TooLarge);
+        }
+        self.expire(now);
+
+        let existing = self
+            .slots
+            .iter()
+            .position(|s| matches!(s, Some(s) if s.id == id));
+        let i = match existing {
+            Some(i) if self.slots[i].as_ref().is_some_and(|s| s.count == count) => i,
+            Some(i) => {
+                self.evict(i);
+                i
+            }
+            None => match self.slots.iter().position(Option::is_none) {
+                Some(i) => i,
+                None => {
+                    // Completed messages go first, then the oldest in flight.
+                    let (i, _) = self
+                        .slots
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, s)| s.as_ref().map(|s| (!s.done, s.started)))
+                        .ok_or(FragmentError::TooLarge)?;
+                    self.evict(i);
+                    i
+                }
+            },
+        };
+        let slot = match &mut self.slots[i] {
+            Some(slot) => slot,
+            empty => empty.insert(Slot {
+                id,
+                count,
+                received: [0; 8],
+                len: None,
+                chunk: None,
+                started: now,
+                done: false,
+                buf: [0; N],
+            }),
+        };
+        if slot.done {
+            return Ok(None);
+        }
+        match (slot.chunk, chunk) {
+            (Some(a), Some(b)) if a != b => return Err(FragmentError::Malformed),
+            (None, _) => slot.chunk = chunk,
+            _ => {}
+        }
+
+        slot.buf[offset..end].copy_from_slice(payload);
+        slot.received[usize::from(index / 32)] |= 1 << (index % 32);
+        if index == count - 1 {
+            slot.len = Some(end);
+        }
+        if !slot.complete() {
+            return Ok(None);
+        }
+        slot.done = true;
+        slot.started = now;
+        Ok(slot.len.map(|len| &slot.buf[..len]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: [u8; 10] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+
+    #[test]
+    fn reassembles_out_of_order() {
+        let mut r = Reassembler::<2, 16>::new(10);
+        let mut frags = fragments(4, &MESSAGE, 9).unwrap();
+        let [a, b, c] = [(); 3].map(|_| frags.next().unwrap());
+        assert_eq!(frags.next(), None);
+        assert_eq!(r.push(c, 0), Ok(None));
+        assert_eq!(r.push(a, 1), Ok(None));
+        assert_eq!(r.push(b, 2), Ok(Some(&MESSAGE[..])));
+        assert_eq!(r.dropped(), 0);
+    }
+
+    #[test]
+    fn rejects_offsets_that_disagree() {
+        let mut r = Reassembler::<2, 16>::new(10);
+        let frag = |index, offset, payload| Fragment {
+            id: 1,
+            index,
+            count: 3,
+            offset,
+            payload,
+        };
+        assert_eq!(
+            r.push(frag(1, 3, &MESSAGE[..4]), 0),
+            Err(FragmentError::Malformed)
+        );
+        assert_eq!(r.push(frag(1, 4, &MESSAGE[4..8]), 0), Ok(None));
+        // The last fragment implies 3-byte fragments, the first one 4.
+        assert_eq!(
+            r.push(frag(2, 6, &MESSAGE[8..]), 0),
+            Err(FragmentError::Malformed)
+        );
+        assert_eq!(
+            r.push(frag(0, 0, &MESSAGE[..3]), 0),
+            Err(FragmentError::Malformed)
+        );
+    }
+
+    #[test]
+    fn ignores_retransmissions_of_completed_messages() {
+        let mut r = Reassembler::<1, 16>::new(10);
+        let mut frags = fragments(4, &MESSAGE, 10).unwrap();
+        let [a, b] = [(); 2].map(|_| frags.next().unwrap());
+        assert_eq!(r.push(a, 0), Ok(None));
+        assert_eq!(r.push(b, 1), Ok(Some(&MESSAGE[..])));
+        assert_eq!(r.push(b, 2), Ok(None));
+        r.expire(20);
+        assert_eq!(r.dropped(), 0);
+        // Once forgotten, the id is free for a new message.
+        assert_eq!(r.push(a, 21), Ok(None));
+    }
+}
@@ -0,0 +1,278 @@
+//! COBS framing for byte-stream transports such as a UART or USB CDC.
+//!
+//! Each frame is COBS-encoded so it contains no zero bytes and is terminated by
+//! a single `0x00` delimiter. A receiver that loses or corrupts bytes only loses
+//! the frame they were in and picks up again at the next delimiter.
+
+use core::mem::MaybeUninit;
+
+use crate::Packet;
+
+/// Largest encoded frame, delimiter included, for a payload of `len` bytes.
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + len / 254 + 2
+}
+
+/// Largest encoded frame for any [`Packet`].
+pub const MAX_PACKET_FRAME_LEN: usize = max_encoded_len(Packet::MAX_SERIALIZED_LEN);
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// The output buffer cannot hold the encoded or decoded frame.
+    BufferTooSmall,
+    /// A frame was longer than the decoder's buffer. All of its bytes were dropped.
+    Overflow { skipped: usize },
+    /// A frame was not valid COBS. All of its bytes were dropped.
+    Malformed { skipped: usize },
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use FrameError as S;
+        match self {
+            S::BufferTooSmall => write!(f, "buffer too small"),
+            S::Overflow { skipped } => write!(f, "frame overflow, skipped {skipped} bytes"),
+            S::Malformed { skipped } => write!(f, "malformed frame, skipped {skipped} bytes"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FrameError {}
+
+/// COBS-encodes `src` into `dst` and appends the delimiter. Returns the number of
+/// bytes written, at most [`max_encoded_len`]`(src.len())`.
+pub fn encode(src: &[u8], dst: &mut [u8]) -> Result<usize, FrameError> {
+    if dst.len() < max_encoded_len(src.len()) {
+        return Err(FrameError::BufferTooSmall);
+    }
+    let mut code_idx = 0;
+    let mut out = 1;
+    let mut code = 1u8;
+    for &b in src {
+        if b != 0 {
+            dst[out] = b;
+            out += 1;
+            code += 1;
+        }
+        if b == 0 || code == 0xff {
+            dst[code_idx] = code;
+            code_idx = out;
+            out += 1;
+            code = 1;
+        }
+    }
+    dst[code_idx] = code;
+    dst[out] = 0;
+    Ok(out + 1)
+}
+
+/// Decodes one COBS frame, without its delimiter, in place and returns the
+/// decoded length. Decoded output never overtakes the input, so the frame is
+/// always `buf[..len]`.
+pub fn decode_in_place(buf: &mut [u8]) -> Result<usize, FrameError> {
+    let mut i = 0;
+    let mut out = 0;
+    while i < buf.len() {
+        let code = usize::from(buf[i]);
+        if code == 0 || i + code > buf.len() || buf[i + 1..i + code].contains(&0) {
+            return Err(FrameError::Malformed { skipped: buf.len() });
+        }
+        buf.copy_within(i + 1..i + code, out);
+        out += code - 1;
+        i += code;
+        if code != 0xff && i < buf.len() {
+            buf[out] = 0;
+            out += 1;
+        }
+    }
+    Ok(out)
+}
+
+/// Serializes `pkt` with [`Packet::serialize`] and frames it into `dst`.
+pub fn encode_packet(pkt: &Packet, dst: &mut [u8]) -> Result<usize, FrameError> {
+    let mut buf = [MaybeUninit::uninit(); Packet::MAX_SERIALIZED_LEN];
+    let len = pkt.serialize(&mut buf);
+    let bytes = unsafe { core::slice::from_raw_parts(buf.as_ptr() as *const u8, len) };
+    encode(bytes, dst)
+}
+
+/// Streaming frame decoder. `N` bounds the encoded frame length, without the
+/// delimiter; [`MAX_PACKET_FRAME_LEN`] fits every [`Packet`].
+///
+/// Bytes are fed in as they arrive. A frame that is too long or not valid COBS is
+/// dropped and reported along with how many bytes were skipped, and decoding
+/// resumes with the byte after the next delimiter.
+#[derive(Clone, Debug)]
+pub struct Decoder<const N: usize> {
+    buf: heapless::Vec<u8, N>,
+    /// Bytes of the current frame that did not fit in `buf`.
+    overflow: usize,
+    /// `buf` holds the frame last returned by `feed`.
+    done: bool,
+}
+
+impl<const N: usize> Default for Decoder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Decoder<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: heapless::Vec::new(),
+            overflow: 0,
+            done: false,
+        }
+    }
+
+    /// Consumes bytes from the front of `bytes` up to and including the next
+    /// delimiter and returns the decoded frame, or `None` if `bytes` ran out
+    /// first. Empty frames, as produced by back-to-back delimiters, are skipped.
+    pub fn feed(&mut self, bytes: &mut &[u8]) -> Option<Result<&[u8], FrameError>> {
+        if core::mem::take(&mut self.done) {
+            self.buf.clear();
+        }
+        loop {
+            let (&b, rest) = bytes.split_first()?;
+            *bytes = rest;
+            if b != 0 {
+                if self.buf.push(b).is_err() {
+                    self.overflow += 1;
+                }
+                continue;
+            }
+            if !self.buf.is_empty() || self.overflow > 0 {
+                break;
+            }
+        }
+
+        self.done = true;
+        if self.overflow > 0 {
+            let skipped = self.buf.len() + core::mem::take(&mut self.overflow);
+            return Some(Err(FrameError::Overflow { skipped }));
+        }
+        match decode_in_place(&mut self.buf) {
+            Ok(len) => {
+                self.buf.truncate(len);
+                Some(Ok(&self.buf))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(src: &[u8]) {
+        let mut enc = [0xaa; 1024];
+        let len = encode(src, &mut enc).unwrap();
+        assert!(len <= max_encoded_len(src.len()));
+        assert_eq!(enc[len - 1], 0);
+        assert!(!enc[..len - 1].contains(&0), "{src:?} encoded with a zero");
+        let dec = decode_in_place(&mut enc[..len - 1]).unwrap();
+        assert_eq!(&enc[..dec], src);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(&[]);
+        round_trip(&[0]);
+        round_trip(&[0, 0]);
+        round_trip(&[1, 2, 0, 3]);
+        round_trip(&[0, 1, 0]);
+        let mut long = [0; 700];
+        for (i, b) in long.iter_mut().enumerate() {
+            *b = (i % 255) as u8 + 1;
+        }
+        for len in [253, 254, 255, 256, 508, 509, 700] {
+            round_trip(&long[..len]);
+        }
+        long[254] = 0;
+        long[600] = 0;
+        round_trip(&long);
+    }
+
+    #[test]
+    fn encode_rejects_a_short_buffer() {
+        let mut dst = [0; 4];
+        assert_eq!(
+            encode(&[1, 2, 3], &mut dst),
+            Err(FrameError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn decode_rejects_malformed_frames() {
+        // Code past the end, a zero code, and a zero inside a block.
+        for frame in [&[5, 1, 2][..], &[0, 1], &[3, 1, 0]] {
+            let mut buf = [0; 3];
+            let buf = &mut buf[..frame.len()];
+            buf.copy_from_slice(frame);
+            let skipped = frame.len();
+            assert_eq!(decode_in_place(buf), Err(FrameError::Malformed { skipped }));
+        }
+    }
+
+    #[test]
+    fn decoder_splits_a_stream() {
+        let mut stream = [0; 64];
+        let mut len = encode(&[1, 0, 2], &mut stream).unwrap();
+        stream[len] = 0;
+        len += 1;
+        len += encode(&[3], &mut stream[len..]).unwrap();
+
+        let mut dec = Decoder::<16>::new();
+        // Fed one byte at a time, frames only complete at their delimiter.
+        let mut frames = 0;
+        for b in &stream[..len] {
+            let bytes = &mut core::slice::from_ref(b);
+            if let Some(frame) = dec.feed(bytes) {
+                let frame = frame.unwrap();
+                assert_eq!(frame, if frames == 0 { &[1, 0, 2][..] } else { &[3] });
+                frames += 1;
+            }
+            assert!(bytes.is_empty());
+        }
+        assert_eq!(frames, 2);
+    }
+
+    #[test]
+    fn decoder_resyncs_after_overflow() {
+        let mut stream = [0; 64];
+        let mut len = encode(&[7; 10], &mut stream).unwrap();
+        len += encode(&[1, 2], &mut stream[len..]).unwrap();
+
+        let mut dec = Decoder::<4>::new();
+        let bytes = &mut &stream[..len];
+        assert_eq!(
+            dec.feed(bytes),
+            Some(Err(FrameError::Overflow { skipped: 11 }))
+        );
+        assert_eq!(dec.feed(bytes), Some(Ok(&[1, 2][..])));
+        assert_eq!(dec.feed(bytes), None);
+    }
+
+    #[test]
+    fn decoder_resyncs_after_corruption() {
+        let mut stream = [0; 64];
+        let mut len = encode(&[1, 2, 3, 4], &mut stream).unwrap();
+        // The first code byte now claims a block longer than the frame.
+        stream[0] = 9;
+        len += encode(&[5, 6], &mut stream[len..]).unwrap();
+
+        let mut dec = Decoder::<16>::new();
+        let bytes = &mut &stream[..len];
+        assert_eq!(
+            dec.feed(bytes),
+            Some(Err(FrameError::Malformed { skipped: 5 }))
+        );
+        assert_eq!(dec.feed(bytes), Some(Ok(&[5, 6][..])));
+        assert_eq!(dec.feed(bytes), None);
+    }
+}
@@ -0,0 +1,61 @@
+//! Protocol version negotiation.
+//!
+//! A host sends [`PacketData::ReadVersion`](crate::PacketData::ReadVersion),
+//! the device answers with its [`Version`], and [`agree`] decides whether the
+//! two can talk. Versions follow semver: peers are compatible when their major
+//! versions match, and while the major version is 0, when their minor versions
+//! match too. Once they can, the host asks for the device's
+//! [`Capabilities`] with `ReadCapabilities`, and [`negotiate`] puts the two
+//! replies together into the feature set both sides go by.
+
+use crate::{Capabilities, Error, PROTOCOL_SEMVER, Version};
+
+/// Whether protocol versions `local` and `peer` can talk to each other.
+pub const fn compatible(local: [u16; 3], peer: [u16; 3]) -> bool {
+    match (local, peer) {
+        ([0, 0, a], [0, 0, b]) => a == b,
+        ([0, a, _], [0, b, _]) => a == b,
+        ([a, _, _], [b, _, _]) => a == b,
+    }
+}
+
+/// The protocol version `local` and `peer` both speak, the older of the two,
+/// or [`Error::VersionMismatch`] if they are not [`compatible`].
+pub fn agree(local: [u16; 3], peer: [u16; 3]) -> Result<[u16; 3], Error> {
+    if !compatible(local, peer) {
+        return Err(Error::VersionMismatch { local, peer });
+    }
+    Ok(local.min(peer))
+}
+
+/// The outcome of a successful handshake.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Negotiated {
+    /// The peer's reply to `ReadVersion`.
+    pub peer: Version,
+    /// The older of the two protocol versions, which both sides speak.
+    pub protocol_semver: [u16; 3],
+    /// The peer's reply to `ReadCapabilities`: what it handles, streams and
+    /// can be configured with.
+    pub features: Capabilities,
+}
+
+/// Checks the peer's `Version` against this crate's [`PROTOCOL_SEMVER`] and
+/// pairs it with the peer's `Capabilities`.
+pub fn negotiate(peer: Version, features: Capabilities) -> Result<Negotiated, Error> {
+    negotiate_with(PROTOCOL_SEMVER, peer, features)
+}
+
+/// Like [`negotiate`], for a local protocol version other than this crate's.
+pub fn negotiate_with(
+    local: [u16; 3],
+    peer: Version,
+    features: Capabilities,
+) -> Result<Negotiated, Error> {
+    Ok(Negotiated {
+        peer,
+        protocol_semver: agree(local, peer.protocol_semver)?,
+        features,
+    })
+}
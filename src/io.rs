@@ -0,0 +1,181 @@
+//! Async packet I/O over [`embedded_io_async`] byte streams.
+//!
+//! [`PacketIo`] frames packets with [`framing`], so firmware on a UART or USB
+//! CDC and a host on the other end share one encoding. With the `futures`
+//! feature, [`FromFutures`] adapts a `futures_io` stream for the host side.
+
+use embedded_io_async::{Read, Write};
+
+use crate::{
+    Error, Packet,
+    framing::{self, Decoder, FrameError, MAX_PACKET_FRAME_LEN},
+};
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug)]
+pub enum RecvError<E> {
+    Io(E),
+    /// The stream ended.
+    Eof,
+    /// A frame was dropped. The stream stays usable.
+    Frame(FrameError),
+    /// A frame did not hold a valid packet. The stream stays usable.
+    Parse(Error),
+}
+
+#[cfg(feature = "std")]
+impl<E: std::fmt::Display> std::fmt::Display for RecvError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use RecvError as S;
+        match self {
+            S::Io(e) => write!(f, "io error: {e}"),
+            S::Eof => write!(f, "end of stream"),
+            S::Frame(e) => write!(f, "{e}"),
+            S::Parse(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: std::error::Error> std::error::Error for RecvError<E> {}
+
+/// Sends and receives framed [`Packet`]s over a byte stream.
+pub struct PacketIo<T> {
+    inner: T,
+    decoder: Decoder<MAX_PACKET_FRAME_LEN>,
+    rx: [u8; 64],
+    /// `rx[start..end]` has been read but not yet fed to `decoder`.
+    start: usize,
+    end: usize,
+}
+
+impl<T> PacketIo<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            decoder: Decoder::new(),
+            rx: [0; 64],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Returns the stream. Bytes read past the last received packet are lost.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Write> PacketIo<T> {
+    /// Writes `pkt` as one frame and flushes the stream.
+    pub async fn send(&mut self, pkt: &Packet) -> Result<(), T::Error> {
+        let mut frame = [0; MAX_PACKET_FRAME_LEN];
+        let len = framing::encode_packet(pkt, &mut frame).expect("frame buffer fits any packet");
+        self.inner.write_all(&frame[..len]).await?;
+        self.inner.flush().await
+    }
+}
+
+impl<T: Read> PacketIo<T> {
+    /// Waits for the next packet.
+    ///
+    /// A dropped frame or a frame that fails to parse is returned as an error,
+    /// and the next call resumes with the following frame. Cancelling `recv`
+    /// loses no bytes as long as cancelling the stream's `read` does not.
+    pub async fn recv(&mut self) -> Result<Packet, RecvError<T::Error>> {
+        loop {
+            let mut pending = &self.rx[self.start..self.end];
+            let frame = self.decoder.feed(&mut pending);
+            self.start = self.end - pending.len();
+            match frame {
+                Some(Ok(mut frame)) => return Packet::parse(&mut frame).map_err(RecvError::Parse),
+                Some(Err(e)) => return Err(RecvError::Frame(e)),
+                None => {}
+            }
+            let n = self.inner.read(&mut self.rx).await.map_err(RecvError::Io)?;
+            if n == 0 {
+                return Err(RecvError::Eof);
+            }
+            self.start = 0;
+            self.end = n;
+        }
+    }
+}
+
+#[cfg(feature = "futures")]
+pub use self::futures::{FromFutures, FuturesError};
+
+#[cfg(feature = "futures")]
+mod futures {
+    use core::{future::poll_fn, pin::Pin};
+
+    use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+    use futures_io::{AsyncRead, AsyncWrite};
+
+    /// Adapts a [`futures_io`] stream to the [`embedded_io_async`] traits, so it
+    /// can be wrapped in a [`PacketIo`](super::PacketIo).
+    #[derive(Clone, Debug)]
+    pub struct FromFutures<T>(pub T);
+
+    impl<T> FromFutures<T> {
+        pub fn into_inner(self) -> T {
+            self.0
+        }
+    }
+
+    /// A [`std::io::Error`] from a [`FromFutures`] stream.
+    #[derive(Debug)]
+    pub struct FuturesError(pub std::io::Error);
+
+    impl std::fmt::Display for FuturesError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl std::error::Error for FuturesError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    impl embedded_io_async::Error for FuturesError {
+        fn kind(&self) -> ErrorKind {
+            self.0.kind().into()
+        }
+    }
+
+    impl<T> ErrorType for FromFutures<T> {
+        type Error = FuturesError;
+    }
+
+    impl<T: AsyncRead + Unpin> Read for FromFutures<T> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, FuturesError> {
+            poll_fn(|cx| Pin::new(&mut self.0).poll_read(cx, buf))
+                .await
+                .map_err(FuturesError)
+        }
+    }
+
+    impl<T: AsyncWrite + Unpin> Write for FromFutures<T> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, FuturesError> {
+            poll_fn(|cx| Pin::new(&mut self.0).poll_write(cx, buf))
+                .await
+                .map_err(FuturesError)
+        }
+
+        async fn flush(&mut self) -> Result<(), FuturesError> {
+            poll_fn(|cx| Pin::new(&mut self.0).poll_flush(cx))
+                .await
+                .map_err(FuturesError)
+        }
+    }
+}
@@ -15,9 +15,32 @@ use core::mem::MaybeUninit;
 use nalgebra::{Isometry3, Point2, Vector3};
 use opencv_ros_camera::RosOpenCvIntrinsics;
 
+#[cfg(all(feature = "std", feature = "minicbor"))]
+pub mod capture;
+#[cfg(feature = "std")]
+pub mod client;
+#[cfg(feature = "tokio")]
+pub mod codec;
 pub mod control;
+pub mod envelope;
+pub mod fragment;
+pub mod framing;
+pub mod handshake;
+#[cfg(feature = "async")]
+pub mod io;
 pub mod mux;
+#[cfg(all(feature = "std", feature = "minicbor"))]
+pub mod pcapng;
+#[cfg(feature = "std")]
+pub mod sim;
 pub mod wire;
+#[cfg(feature = "std")]
+pub mod wireshark;
+/// Reads a value from the front of `bytes` and advances past it.
+///
+/// Implementations never panic, whatever the input: a short slice yields
+/// [`Error::UnexpectedEof`] and malformed contents yield one of the other
+/// [`Error`] variants. On error, how far `bytes` was advanced is unspecified.
 pub trait Parse: Sized {
     fn parse(bytes: &mut &[u8]) -> Result<Self, Error>;
 }
@@ -46,6 +69,15 @@ fn push(buf: &mut &mut [MaybeUninit<u8>], data: &[u8]) {
     *buf = &mut core::mem::take(buf)[data.len()..];
 }
 
+#[inline]
+fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], Error> {
+    let (head, rest) = bytes
+        .split_first_chunk::<N>()
+        .ok_or(Error::UnexpectedEof { packet_type: None })?;
+    *bytes = rest;
+    Ok(*head)
+}
+
 #[repr(C)]
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -59,79 +91,302 @@ pub struct Packet {
     pub id: u8,
 }
 
-#[repr(C)]
-#[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
-#[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-#[cfg_attr(feature = "minicbor", derive(Encode, Decode, CborLen))]
-#[derive(Clone, Debug)]
-pub enum PacketData {
-    #[cfg_attr(feature = "minicbor", n(0))]
-    WriteRegister(#[cfg_attr(feature = "minicbor", n(0))] WriteRegister),
-    #[cfg_attr(feature = "minicbor", n(1))]
-    ReadRegister(#[cfg_attr(feature = "minicbor", n(0))] Register),
-    #[cfg_attr(feature = "minicbor", n(2))]
-    ReadRegisterResponse(#[cfg_attr(feature = "minicbor", n(0))] ReadRegisterResponse),
-    #[cfg_attr(feature = "minicbor", n(3))]
-    WriteConfig(
-        #[cfg_attr(feature = "minicbor", n(0))]
-        #[cfg_attr(feature = "minicbor", cbor(with = "serde_cbor_with"))]
-        GeneralConfig,
-    ),
-    #[cfg_attr(feature = "minicbor", n(4))]
-    ReadConfig(#[cfg_attr(feature = "minicbor", n(0))] ConfigKind),
-    #[cfg_attr(feature = "minicbor", n(5))]
-    ReadConfigResponse(
-        #[cfg_attr(feature = "minicbor", n(0))]
-        #[cfg_attr(feature = "minicbor", cbor(with = "serde_cbor_with"))]
-        GeneralConfig,
-    ),
-    #[cfg_attr(feature = "minicbor", n(6))]
-    ReadProp(#[cfg_attr(feature = "minicbor", n(0))] PropKind),
-    #[cfg_attr(feature = "minicbor", n(7))]
-    ReadPropResponse(#[cfg_attr(feature = "minicbor", n(0))] Props),
-    #[cfg_attr(feature = "minicbor", n(8))]
-    ObjectReportRequest(),
-    #[cfg_attr(feature = "minicbor", n(9))]
-    ObjectReport(#[cfg_attr(feature = "minicbor", n(0))] ObjectReport),
-    #[cfg_attr(feature = "minicbor", n(10))]
-    CombinedMarkersReport(#[cfg_attr(feature = "minicbor", n(0))] CombinedMarkersReport),
-    #[cfg_attr(feature = "minicbor", n(11))]
-    PocMarkersReport(#[cfg_attr(feature = "minicbor", n(0))] PocMarkersReport),
-    #[cfg_attr(feature = "minicbor", n(12))]
-    AccelReport(#[cfg_attr(feature = "minicbor", n(0))] AccelReport),
-    #[cfg_attr(feature = "minicbor", n(13))]
-    ImpactReport(#[cfg_attr(feature = "minicbor", n(0))] ImpactReport),
-    #[cfg_attr(feature = "minicbor", n(14))]
-    StreamUpdate(#[cfg_attr(feature = "minicbor", n(0))] StreamUpdate),
-    #[cfg_attr(feature = "minicbor", n(15))]
-    FlashSettings(),
-    #[cfg_attr(feature = "minicbor", n(16))]
-    Ack(),
-    #[cfg_attr(feature = "minicbor", n(17))]
-    WriteMode(#[cfg_attr(feature = "minicbor", n(0))] Mode),
-    #[cfg_attr(feature = "minicbor", n(18))]
-    ReadVersion(),
-    #[cfg_attr(feature = "minicbor", n(19))]
-    ReadVersionResponse(#[cfg_attr(feature = "minicbor", n(0))] Version),
-    #[cfg_attr(feature = "minicbor", n(20))]
-    Vendor(
-        #[cfg_attr(feature = "minicbor", n(0))] u8,
-        #[cfg_attr(feature = "minicbor", n(1))] VendorData,
-    ),
-    #[cfg_attr(feature = "minicbor", n(21))]
-    BatteryReport(#[cfg_attr(feature = "minicbor", n(0))] BatteryReport),
-    #[cfg_attr(feature = "minicbor", n(22))]
-    SetDeviceName(
-        #[cfg_attr(feature = "minicbor", n(0))]
-        #[cfg_attr(feature = "minicbor", cbor(with = "heapless_str32_cbor"))]
-        heapless::String<32>,
-    ),
-    #[cfg_attr(feature = "minicbor", n(23))]
-    SetDeviceNameResponse(
-        #[cfg_attr(feature = "minicbor", n(0))]
-        Result<(), ()>,
-    ),
+/// Declares every packet once, as `Name = id, payload type;`, and generates
+/// [`PacketData`], [`PacketType`] and the tables mapping between them and the
+/// wire id, so they can't fall out of step.
+///
+/// `data` lists the [`PacketData`] variants and `types` the [`PacketType`]
+/// variants. Each variant's CBOR index is its position in its list, which is
+/// also the variant index serde uses, so new packets go at the end of both.
+/// The two lists keep their historical orders: `PocMarkersReport` comes after
+/// `Ack` in `types`, and `types` also declares the markers, ids with no packet
+/// of their own, as `Name = id;`. Vendor packets take the ids strictly between
+/// `VendorStart` and `VendorEnd`, and `Vendor;` places their variant.
+macro_rules! packets {
+    (@bind $name:ident, $x:tt) => { PacketData::$name() };
+    (@bind $name:ident, $x:tt, $payload:ty) => { PacketData::$name($x) };
+    (@payload $x:tt) => { &() };
+    (@payload $x:tt, $payload:ty) => { $x };
+    (@size) => { 0 };
+    (@size $payload:ty) => { <$payload as Serialize>::SIZE };
+    (data { $($data:tt)* } types { $($types:tt)* }) => {
+        packets!(@data [] [] [
+            0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30
+            31 32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47 48 49 50 51 52 53 54 55 56 57 58
+            59 60 61 62 63
+        ] { $($data)* } { $($types)* });
+    };
+
+    // Collects the `PacketData` variants and the packet rows, numbering the
+    // variants from the list of indices.
+    (@data [$($var:tt)*] [$($row:tt)*] [$n:tt $($next:tt)*] {
+        Vendor;
+        $($rest:tt)*
+    } $types:tt) => {
+        packets!(@data [
+            $($var)*
+            #[cfg_attr(feature = "minicbor", n($n))]
+            Vendor(
+                #[cfg_attr(feature = "minicbor", n(0))] u8,
+                #[cfg_attr(feature = "minicbor", n(1))] VendorData,
+            ),
+        ] [$($row)*] [$($next)*] { $($rest)* } $types);
+    };
+    (@data [$($var:tt)*] [$($row:tt)*] [$n:tt $($next:tt)*] {
+        $name:ident = $id:literal $(, $(#[$field:meta])* $payload:ty)?;
+        $($rest:tt)*
+    } $types:tt) => {
+        packets!(@data [
+            $($var)*
+            #[cfg_attr(feature = "minicbor", n($n))]
+            $name($(#[cfg_attr(feature = "minicbor", n(0))] $(#[$field])* $payload)?),
+        ] [$($row)* $name = $id $(, $payload)?;] [$($next)*] { $($rest)* } $types);
+    };
+    (@data [$($var:tt)*] [$($row:tt)*] [$($next:tt)*] {} $types:tt) => {
+        #[repr(C)]
+        #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
+        #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        #[cfg_attr(feature = "minicbor", derive(Encode, Decode, CborLen))]
+        #[derive(Clone, Debug)]
+        pub enum PacketData {
+            $($var)*
+        }
+
+        packets!(@types [] [] [
+            0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30
+            31 32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47 48 49 50 51 52 53 54 55 56 57 58
+            59 60 61 62 63
+        ] $types [$($row)*]);
+    };
+
+    // Collects the `PacketType` variants and the markers, numbered like the
+    // `PacketData` variants.
+    (@types [$($var:tt)*] [$($marker:tt)*] [$n:tt $($next:tt)*] {
+        Vendor;
+        $($rest:tt)*
+    } $rows:tt) => {
+        packets!(@types [
+            $($var)*
+            #[cfg_attr(feature = "minicbor", n($n))]
+            Vendor(#[cfg_attr(feature = "minicbor", n(0))] u8),
+        ] [$($marker)*] [$($next)*] { $($rest)* } $rows);
+    };
+    (@types [$($var:tt)*] [$($marker:tt)*] [$n:tt $($next:tt)*] {
+        $name:ident = $id:literal;
+        $($rest:tt)*
+    } $rows:tt) => {
+        packets!(@types [
+            $($var)*
+            #[cfg_attr(feature = "minicbor", n($n))]
+            $name(),
+        ] [$($marker)* $name = $id;] [$($next)*] { $($rest)* } $rows);
+    };
+    (@types [$($var:tt)*] [$($marker:tt)*] [$n:tt $($next:tt)*] {
+        $name:ident;
+        $($rest:tt)*
+    } $rows:tt) => {
+        packets!(@types [
+            $($var)*
+            #[cfg_attr(feature = "minicbor", n($n))]
+            $name(),
+        ] [$($marker)*] [$($next)*] { $($rest)* } $rows);
+    };
+    (@types [$($var:tt)*] [$($marker:tt)*] [$($next:tt)*] {} $rows:tt) => {
+        #[repr(C)]
+        #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
+        #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        #[cfg_attr(feature = "minicbor", derive(Encode, Decode, CborLen))]
+        #[derive(Copy, Clone, Debug)]
+        pub enum PacketType {
+            $($var)*
+        }
+
+        packets!(@tables $rows [$($marker)*]);
+    };
+
+    (@tables [
+        $($name:ident = $id:literal $(, $payload:ty)?;)*
+    ] [
+        $($marker:ident = $marker_id:literal;)*
+    ]) => {
+        impl PacketType {
+            const fn from_id(id: u8) -> Option<Self> {
+                Some(match id {
+                    $($id => Self::$name(),)*
+                    $($marker_id => Self::$marker(),)*
+                    id if Self::VendorStart().id() < id && id < Self::VendorEnd().id() => {
+                        Self::Vendor(id)
+                    }
+                    _ => return None,
+                })
+            }
+
+            const fn id(self) -> u8 {
+                match self {
+                    $(Self::$name() => $id,)*
+                    $(Self::$marker() => $marker_id,)*
+                    Self::Vendor(id) => id,
+                }
+            }
+        }
+
+        // Every id maps to a type that maps back to it, and every declared type
+        // is reachable from its id.
+        const _: () = {
+            let mut id = 0u8;
+            loop {
+                if let Some(ty) = PacketType::from_id(id) {
+                    assert!(ty.id() == id);
+                }
+                if id == u8::MAX {
+                    break;
+                }
+                id += 1;
+            }
+            $(assert!(matches!(PacketType::from_id($id), Some(PacketType::$name())));)*
+            $(assert!(matches!(PacketType::from_id($marker_id), Some(PacketType::$marker())));)*
+        };
+
+        impl TryFrom<u8> for PacketType {
+            type Error = Error;
+            fn try_from(n: u8) -> Result<Self, Self::Error> {
+                Self::from_id(n).ok_or(Error::UnrecognizedPacketId(n))
+            }
+        }
+
+        impl From<PacketType> for u8 {
+            fn from(ty: PacketType) -> u8 {
+                ty.id()
+            }
+        }
+
+        impl PacketData {
+            pub fn ty(&self) -> PacketType {
+                match self {
+                    $(packets!(@bind $name, _ $(, $payload)?) => PacketType::$name(),)*
+                    PacketData::Vendor(n, _) => PacketType::Vendor(*n),
+                }
+            }
+        }
+
+        impl Packet {
+            fn parse_data(ty: PacketType, payload: &mut &[u8]) -> Result<PacketData, Error> {
+                Ok(match ty {
+                    $(
+                        PacketType::$name() => {
+                            PacketData::$name($(<$payload as Parse>::parse(payload)?)?)
+                        }
+                    )*
+                    PacketType::Vendor(n) => PacketData::Vendor(n, Parse::parse(payload)?),
+                    $(PacketType::$marker())|* => {
+                        return Err(Error::UnrecognizedPacketId(ty.into()));
+                    }
+                })
+            }
+
+            /// Serializes the packet in the layout written by [`Packet::serialize_parts`] and
+            /// returns the number of bytes written, which is always [`Packet::serialized_len`].
+            ///
+            /// [`Packet::parse`] reads the result back into an equal packet, except where
+            /// a field holds more than its wire encoding does:
+            ///
+            /// - `AccelReport` is quantized to the wire's fixed-point resolution, and
+            ///   camera and stereo configs go through float conversions.
+            /// - Marker points keep the low 12 bits of each coordinate.
+            /// - `MotData` keeps the low 12 bits of `cx` and `cy`, the low 4 bits of
+            ///   `radius` and `range` and the low 7 bits of each boundary.
+            ///
+            /// Panics if `buf` is shorter than [`Packet::serialized_len`].
+            pub fn serialize(&self, buf: &mut [MaybeUninit<u8>]) -> usize {
+                let (id, ty) = (self.id, self.ty());
+                match &self.data {
+                    $(
+                        packets!(@bind $name, x $(, $payload)?) => {
+                            Self::serialize_parts(id, ty, packets!(@payload x $(, $payload)?), buf)
+                        }
+                    )*
+                    PacketData::Vendor(_, x) => Self::serialize_parts(id, ty, x, buf),
+                }
+            }
+
+            /// Number of bytes [`Packet::serialize`] writes, header included.
+            pub fn serialized_len(&self) -> usize {
+                let payload = match &self.data {
+                    $(packets!(@bind $name, _ $(, $payload)?) => packets!(@size $($payload)?),)*
+                    PacketData::Vendor(_, _) => VendorData::SIZE,
+                };
+                payload + 4
+            }
+        }
+    };
+}
+
+packets! {
+    data {
+        WriteRegister = 0x00, WriteRegister;
+        ReadRegister = 0x01, Register;
+        ReadRegisterResponse = 0x02, ReadRegisterResponse;
+        WriteConfig = 0x03,
+            #[cfg_attr(feature = "minicbor", cbor(with = "serde_cbor_with"))] GeneralConfig;
+        ReadConfig = 0x04, ConfigKind;
+        ReadConfigResponse = 0x05,
+            #[cfg_attr(feature = "minicbor", cbor(with = "serde_cbor_with"))] GeneralConfig;
+        ReadProp = 0x06, PropKind;
+        ReadPropResponse = 0x07, Props;
+        ObjectReportRequest = 0x08;
+        ObjectReport = 0x09, ObjectReport;
+        CombinedMarkersReport = 0x0a, CombinedMarkersReport;
+        PocMarkersReport = 0x10, PocMarkersReport;
+        AccelReport = 0x0b, AccelReport;
+        ImpactReport = 0x0c, ImpactReport;
+        StreamUpdate = 0x0d, StreamUpdate;
+        FlashSettings = 0x0e;
+        Ack = 0x0f;
+        WriteMode = 0x11, Mode;
+        ReadVersion = 0x12;
+        ReadVersionResponse = 0x13, Version;
+        Vendor;
+        BatteryReport = 0x15, BatteryReport;
+        SetDeviceName = 0x16,
+            #[cfg_attr(feature = "minicbor", cbor(with = "heapless_str32_cbor"))]
+            heapless::String<32>;
+        SetDeviceNameResponse = 0x17, Result<(), ()>;
+        ReadCapabilities = 0x18;
+        ReadCapabilitiesResponse = 0x19, Capabilities;
+    }
+    types {
+        WriteRegister;
+        ReadRegister;
+        ReadRegisterResponse;
+        WriteConfig;
+        ReadConfig;
+        ReadConfigResponse;
+        ReadProp;
+        ReadPropResponse;
+        ObjectReportRequest;
+        ObjectReport;
+        CombinedMarkersReport;
+        AccelReport;
+        ImpactReport;
+        StreamUpdate;
+        FlashSettings;
+        Ack;
+        PocMarkersReport;
+        WriteMode;
+        ReadVersion;
+        ReadVersionResponse;
+        End = 0x14;
+        VendorStart = 0x80;
+        Vendor;
+        VendorEnd = 0xff;
+        BatteryReport;
+        SetDeviceName;
+        SetDeviceNameResponse;
+        ReadCapabilities;
+        ReadCapabilitiesResponse;
+    }
 }
 
 #[repr(C)]
@@ -178,7 +433,7 @@ impl TryFrom<u8> for StreamUpdateAction {
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "minicbor", derive(Encode, Decode, CborLen))]
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, enumn::N, PartialEq)]
 pub enum Mode {
     #[cfg_attr(feature = "minicbor", n(0))]
     Object,
@@ -186,6 +441,13 @@ pub enum Mode {
     Image,
 }
 
+impl TryFrom<u8> for Mode {
+    type Error = Error;
+    fn try_from(n: u8) -> Result<Self, Self::Error> {
+        Self::n(n).ok_or(Error::InvalidBitPattern)
+    }
+}
+
 #[repr(C)]
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -199,28 +461,181 @@ pub struct Version {
     pub firmware_semver: [u16; 3],
 }
 
+/// Protocol version of this crate, stamped into [`Version::protocol_semver`].
+pub const PROTOCOL_SEMVER: [u16; 3] = {
+    const PROTO_MAJOR: u16 = match u16::from_str_radix(core::env!("CARGO_PKG_VERSION_MAJOR"), 10) {
+        Ok(v) => v,
+        Err(_) => panic!("Invalid CARGO_PKG_VERSION_MAJOR"),
+    };
+    const PROTO_MINOR: u16 = match u16::from_str_radix(core::env!("CARGO_PKG_VERSION_MINOR"), 10) {
+        Ok(v) => v,
+        Err(_) => panic!("Invalid CARGO_PKG_VERSION_MINOR"),
+    };
+    const PROTO_PATCH: u16 = match u16::from_str_radix(core::env!("CARGO_PKG_VERSION_PATCH"), 10) {
+        Ok(v) => v,
+        Err(_) => panic!("Invalid CARGO_PKG_VERSION_PATCH"),
+    };
+    [PROTO_MAJOR, PROTO_MINOR, PROTO_PATCH]
+};
+
 impl Version {
     pub fn new(firmware_semver: [u16; 3]) -> Self {
-        const PROTO_MAJOR: u16 =
-            match u16::from_str_radix(core::env!("CARGO_PKG_VERSION_MAJOR"), 10) {
-                Ok(v) => v,
-                Err(_) => panic!("Invalid CARGO_PKG_VERSION_MAJOR"),
-            };
-        const PROTO_MINOR: u16 =
-            match u16::from_str_radix(core::env!("CARGO_PKG_VERSION_MINOR"), 10) {
-                Ok(v) => v,
-                Err(_) => panic!("Invalid CARGO_PKG_VERSION_MINOR"),
-            };
-        const PROTO_PATCH: u16 =
-            match u16::from_str_radix(core::env!("CARGO_PKG_VERSION_PATCH"), 10) {
-                Ok(v) => v,
-                Err(_) => panic!("Invalid CARGO_PKG_VERSION_PATCH"),
-            };
         Self {
-            protocol_semver: [PROTO_MAJOR, PROTO_MINOR, PROTO_PATCH],
+            protocol_semver: PROTOCOL_SEMVER,
             firmware_semver,
         }
+    }
+}
+
+/// A set of packet types, with one bit for each of the 256 type ids, vendor
+/// ids included.
+#[repr(C)]
+#[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "minicbor", derive(Encode, Decode, CborLen))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PacketTypeSet {
+    /// Bit `n % 64` of word `n / 64` is set if type id `n` is in the set.
+    #[cfg_attr(feature = "minicbor", n(0))]
+    pub bits: [u64; 4],
+}
+
+impl PacketTypeSet {
+    pub const fn empty() -> Self {
+        Self { bits: [0; 4] }
+    }
+
+    /// Every type id, including ids no [`PacketType`] is declared for.
+    pub const fn all() -> Self {
+        Self {
+            bits: [u64::MAX; 4],
+        }
+    }
+
+    const fn word_and_bit(ty: PacketType) -> (usize, u64) {
+        let id = ty.id();
+        ((id / 64) as usize, 1 << (id % 64))
+    }
+
+    pub fn insert(&mut self, ty: PacketType) {
+        let (word, bit) = Self::word_and_bit(ty);
+        self.bits[word] |= bit;
+    }
+
+    pub fn remove(&mut self, ty: PacketType) {
+        let (word, bit) = Self::word_and_bit(ty);
+        self.bits[word] &= !bit;
+    }
+
+    pub const fn contains(&self, ty: PacketType) -> bool {
+        let (word, bit) = Self::word_and_bit(ty);
+        self.bits[word] & bit != 0
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        matches!(self.bits, [0, 0, 0, 0])
+    }
+}
+
+impl FromIterator<PacketType> for PacketTypeSet {
+    fn from_iter<I: IntoIterator<Item = PacketType>>(iter: I) -> Self {
+        let mut set = Self::empty();
+        for ty in iter {
+            set.insert(ty);
+        }
+        set
+    }
+}
+
+/// What a device or dongle supports, as reported in answer to
+/// `ReadCapabilities`. Hosts should check these rather than infer features
+/// from the [`ProductId`].
+#[repr(C)]
+#[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "minicbor", derive(Encode, Decode, CborLen))]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Capabilities {
+    /// The packet types that are handled.
+    #[cfg_attr(feature = "minicbor", n(0))]
+    pub packet_types: PacketTypeSet,
+    /// The packet types that can be streamed with `StreamUpdate`.
+    #[cfg_attr(feature = "minicbor", n(1))]
+    pub streams: PacketTypeSet,
+    /// Bit `n` is set if `ConfigKind` `n` can be read and written.
+    #[cfg_attr(feature = "minicbor", n(2))]
+    pub config_kinds: u32,
+    /// Bit `n` is set if `TransportMode` `n` is available.
+    #[cfg_attr(feature = "minicbor", n(3))]
+    pub transports: u8,
+    /// Whether the device also has a control endpoint, which carries
+    /// [`DeviceMsg`](control::device::DeviceMsg)s.
+    #[cfg_attr(feature = "minicbor", n(4))]
+    pub control_endpoint: bool,
+}
+
+impl PacketType {
+    /// Whether packets of this type only ever answer a request, unlike reports
+    /// a device also sends unprompted.
+    pub fn is_response(self) -> bool {
+        matches!(
+            self,
+            PacketType::ReadRegisterResponse()
+                | PacketType::ReadConfigResponse()
+                | PacketType::ReadPropResponse()
+                | PacketType::Ack()
+                | PacketType::ReadVersionResponse()
+                | PacketType::SetDeviceNameResponse()
+                | PacketType::ReadCapabilitiesResponse()
+        )
+    }
 }
+
+impl Capabilities {
+    pub fn insert_packet_type(&mut self, ty: PacketType) {
+        self.packet_types.insert(ty);
+    }
+
+    pub fn insert_stream(&mut self, ty: PacketType) {
+        self.streams.insert(ty);
+    }
+
+    pub fn insert_config_kind(&mut self, kind: ConfigKind) {
+        self.config_kinds |= 1 << kind as u8;
+    }
+
+    pub fn insert_transport(&mut self, mode: control::device::TransportMode) {
+        self.transports |= 1 << mode as u8;
+    }
+
+    pub fn supports_packet_type(&self, ty: PacketType) -> bool {
+        self.packet_types.contains(ty)
+    }
+
+    pub fn supports_stream(&self, ty: PacketType) -> bool {
+        self.streams.contains(ty)
+    }
+
+    pub fn supports_config_kind(&self, kind: ConfigKind) -> bool {
+        self.config_kinds & (1 << kind as u8) != 0
+    }
+
+    pub fn supports_transport(&self, mode: control::device::TransportMode) -> bool {
+        self.transports & (1 << mode as u8) != 0
+    }
+
+    /// Whether the host can open the device's control endpoint. Replaces
+    /// [`ProductId::supports_control_endpoint`].
+    pub fn supports_control_endpoint(&self) -> bool {
+        self.control_endpoint
+    }
+
+    /// Whether the device handles `request`, judged by its packet type.
+    pub fn supports_request(&self, request: &PacketData) -> bool {
+        self.supports_packet_type(request.ty())
+    }
 }
 
 #[repr(C)]
@@ -331,7 +746,7 @@ pub struct GyroConfig {
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "minicbor", derive(Encode, Decode, CborLen))]
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, enumn::N, PartialEq)]
 pub enum ConfigKind {
     #[cfg_attr(feature = "minicbor", n(0))]
     ImpactThreshold = 0,
@@ -349,6 +764,13 @@ pub enum ConfigKind {
     StereoIso = 6,
 }
 
+impl TryFrom<u8> for ConfigKind {
+    type Error = Error;
+    fn try_from(n: u8) -> Result<Self, Self::Error> {
+        Self::n(n).ok_or(Error::UnrecognizedConfigKind(n))
+    }
+}
+
 #[repr(C)]
 #[cfg_attr(feature = "pyo3", pyo3::pyclass)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -373,7 +795,7 @@ pub enum GeneralConfig {
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "minicbor", derive(Encode, Decode, CborLen))]
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, enumn::N, PartialEq)]
 pub enum PropKind {
     #[cfg_attr(feature = "minicbor", n(0))]
     Uuid = 0,
@@ -385,6 +807,13 @@ pub enum PropKind {
     Version = 3,
 }
 
+impl TryFrom<u8> for PropKind {
+    type Error = Error;
+    fn try_from(n: u8) -> Result<Self, Self::Error> {
+        Self::n(n).ok_or(Error::UnrecognizedPropKind(n))
+    }
+}
+
 #[repr(C)]
 #[cfg_attr(feature = "pyo3", pyo3::pyclass)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -567,6 +996,24 @@ pub enum Error {
     UnrecognizedStreamUpdateAction(#[cfg_attr(feature = "minicbor", n(0))] u8),
     #[cfg_attr(feature = "minicbor", n(4))]
     InvalidBitPattern,
+    #[cfg_attr(feature = "minicbor", n(5))]
+    UnrecognizedConfigKind(#[cfg_attr(feature = "minicbor", n(0))] u8),
+    #[cfg_attr(feature = "minicbor", n(6))]
+    UnrecognizedPropKind(#[cfg_attr(feature = "minicbor", n(0))] u8),
+    #[cfg_attr(feature = "minicbor", n(7))]
+    ChecksumMismatch {
+        #[cfg_attr(feature = "minicbor", n(0))]
+        expected: u32,
+        #[cfg_attr(feature = "minicbor", n(1))]
+        actual: u32,
+    },
+    #[cfg_attr(feature = "minicbor", n(8))]
+    VersionMismatch {
+        #[cfg_attr(feature = "minicbor", n(0))]
+        local: [u16; 3],
+        #[cfg_attr(feature = "minicbor", n(1))]
+        peer: [u16; 3],
+    },
 }
 
 #[cfg(feature = "std")]
@@ -584,6 +1031,18 @@ impl Display for Error {
                 write!(f, "unrecognized stream update action {n}")
             }
             S::InvalidBitPattern => write!(f, "invalid bit pattern"),
+            S::UnrecognizedConfigKind(n) => write!(f, "unrecognized config kind {n}"),
+            S::UnrecognizedPropKind(n) => write!(f, "unrecognized prop kind {n}"),
+            S::ChecksumMismatch { expected, actual } => {
+                write!(f, "checksum mismatch, expected {expected:#x}, got {actual:#x}")
+            }
+            S::VersionMismatch {
+                local: [a, b, c],
+                peer: [x, y, z],
+            } => write!(
+                f,
+                "incompatible protocol version {x}.{y}.{z}, expected {a}.{b}.{c}"
+            ),
         }
     }
 }
@@ -614,170 +1073,50 @@ impl TryFrom<u8> for Port {
     }
 }
 
-#[repr(C)]
-#[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
-#[cfg_attr(feature = "defmt", derive(defmt::Format))]
-#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
-#[cfg_attr(feature = "minicbor", derive(Encode, Decode, CborLen))]
-#[derive(Copy, Clone, Debug)]
-pub enum PacketType {
-    #[cfg_attr(feature = "minicbor", n(0))]
-    WriteRegister(),
-    #[cfg_attr(feature = "minicbor", n(1))]
-    ReadRegister(),
-    #[cfg_attr(feature = "minicbor", n(2))]
-    ReadRegisterResponse(),
-    #[cfg_attr(feature = "minicbor", n(3))]
-    WriteConfig(),
-    #[cfg_attr(feature = "minicbor", n(4))]
-    ReadConfig(),
-    #[cfg_attr(feature = "minicbor", n(5))]
-    ReadConfigResponse(),
-    #[cfg_attr(feature = "minicbor", n(6))]
-    ReadProp(),
-    #[cfg_attr(feature = "minicbor", n(7))]
-    ReadPropResponse(),
-    #[cfg_attr(feature = "minicbor", n(8))]
-    ObjectReportRequest(),
-    #[cfg_attr(feature = "minicbor", n(9))]
-    ObjectReport(),
-    #[cfg_attr(feature = "minicbor", n(10))]
-    CombinedMarkersReport(),
-    #[cfg_attr(feature = "minicbor", n(11))]
-    AccelReport(),
-    #[cfg_attr(feature = "minicbor", n(12))]
-    ImpactReport(),
-    #[cfg_attr(feature = "minicbor", n(13))]
-    StreamUpdate(),
-    #[cfg_attr(feature = "minicbor", n(14))]
-    FlashSettings(),
-    #[cfg_attr(feature = "minicbor", n(15))]
-    Ack(),
-    #[cfg_attr(feature = "minicbor", n(16))]
-    PocMarkersReport(),
-    #[cfg_attr(feature = "minicbor", n(17))]
-    WriteMode(),
-    #[cfg_attr(feature = "minicbor", n(18))]
-    ReadVersion(),
-    #[cfg_attr(feature = "minicbor", n(19))]
-    ReadVersionResponse(),
-    #[cfg_attr(feature = "minicbor", n(20))]
-    End(),
-    #[cfg_attr(feature = "minicbor", n(21))]
-    VendorStart(),
-    #[cfg_attr(feature = "minicbor", n(22))]
-    Vendor(#[cfg_attr(feature = "minicbor", n(0))] u8),
-    #[cfg_attr(feature = "minicbor", n(23))]
-    VendorEnd(),
-    #[cfg_attr(feature = "minicbor", n(24))]
-    BatteryReport(),
-    #[cfg_attr(feature = "minicbor", n(25))]
-    SetDeviceName(),
-    #[cfg_attr(feature = "minicbor", n(26))]
-    SetDeviceNameResponse(),
-}
-
-impl TryFrom<u8> for PacketType {
-    type Error = Error;
-    fn try_from(n: u8) -> Result<Self, Self::Error> {
-        match n {
-            0x00 => Ok(Self::WriteRegister()),
-            0x01 => Ok(Self::ReadRegister()),
-            0x02 => Ok(Self::ReadRegisterResponse()),
-            0x03 => Ok(Self::WriteConfig()),
-            0x04 => Ok(Self::ReadConfig()),
-            0x05 => Ok(Self::ReadConfigResponse()),
-            0x06 => Ok(Self::ReadProp()),
-            0x07 => Ok(Self::ReadPropResponse()),
-            0x08 => Ok(Self::ObjectReportRequest()),
-            0x09 => Ok(Self::ObjectReport()),
-            0x0a => Ok(Self::CombinedMarkersReport()),
-            0x0b => Ok(Self::AccelReport()),
-            0x0c => Ok(Self::ImpactReport()),
-            0x0d => Ok(Self::StreamUpdate()),
-            0x0e => Ok(Self::FlashSettings()),
-            0x0f => Ok(Self::Ack()),
-            0x10 => Ok(Self::PocMarkersReport()),
-            0x11 => Ok(Self::WriteMode()),
-            0x12 => Ok(Self::ReadVersion()),
-            0x13 => Ok(Self::ReadVersionResponse()),
-            0x14 => Ok(Self::End()),
-            0x15 => Ok(Self::BatteryReport()),
-            0x16 => Ok(Self::SetDeviceName()),
-            0x17 => Ok(Self::SetDeviceNameResponse()),
-            0x80 => Ok(Self::VendorStart()),
-            0xff => Ok(Self::VendorEnd()),
-            n if (PacketType::VendorStart().into()..PacketType::VendorEnd().into())
-                .contains(&n) =>
-            {
-                Ok(Self::Vendor(n))
-            }
-            _ => Err(Error::UnrecognizedPacketId(n)),
-        }
+impl Packet {
+    /// Upper bound on [`Packet::serialized_len`].
+    pub const MAX_SERIALIZED_LEN: usize = ObjectReport::SIZE + 4;
+
+    pub fn ty(&self) -> PacketType {
+        self.data.ty()
     }
-}
 
-impl From<PacketType> for u8 {
-    fn from(ty: PacketType) -> u8 {
-        match ty {
-            PacketType::WriteRegister() => 0x00,
-            PacketType::ReadRegister() => 0x01,
-            PacketType::ReadRegisterResponse() => 0x02,
-            PacketType::WriteConfig() => 0x03,
-            PacketType::ReadConfig() => 0x04,
-            PacketType::ReadConfigResponse() => 0x05,
-            PacketType::ReadProp() => 0x06,
-            PacketType::ReadPropResponse() => 0x07,
-            PacketType::ObjectReportRequest() => 0x08,
-            PacketType::ObjectReport() => 0x09,
-            PacketType::CombinedMarkersReport() => 0x0a,
-            PacketType::AccelReport() => 0x0b,
-            PacketType::ImpactReport() => 0x0c,
-            PacketType::StreamUpdate() => 0x0d,
-            PacketType::FlashSettings() => 0x0e,
-            PacketType::Ack() => 0x0f,
-            PacketType::PocMarkersReport() => 0x10,
-            PacketType::WriteMode() => 0x11,
-            PacketType::ReadVersion() => 0x12,
-            PacketType::ReadVersionResponse() => 0x13,
-            PacketType::End() => 0x14,
-            PacketType::BatteryReport() => 0x15,
-            PacketType::SetDeviceName() => 0x16,
-            PacketType::SetDeviceNameResponse() => 0x17,
-            PacketType::VendorStart() => 0x80,
-            PacketType::VendorEnd() => 0xff,
-            PacketType::Vendor(n) => n,
+    /// Parses a packet in the `[words_le, ty, id]` header layout written by
+    /// [`Packet::serialize_parts`]. `words` counts the whole packet, header
+    /// included, in 16-bit words; `bytes` is advanced past all of them.
+    ///
+    /// Like the [`Parse`] impls it is built on, this never panics.
+    pub fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        use Error as E;
+        let [words0, words1, ty, id, ..] = **bytes else {
+            return Err(E::UnexpectedEof { packet_type: None });
+        };
+        let ty = PacketType::try_from(ty)?;
+        let len = usize::from(u16::from_le_bytes([words0, words1])) * 2;
+        if len < 4 || bytes.len() < len {
+            return Err(E::UnexpectedEof {
+                packet_type: Some(ty),
+            });
         }
+        let payload = &mut &bytes[4..len];
+        *bytes = &bytes[len..];
+
+        let data = Self::parse_data(ty, payload).map_err(|e| match e {
+            E::UnexpectedEof { packet_type: None } => E::UnexpectedEof {
+                packet_type: Some(ty),
+            },
+            e => e,
+        })?;
+        Ok(Self { data, id })
     }
-}
 
-impl Packet {
-    pub fn ty(&self) -> PacketType {
-        match self.data {
-            PacketData::WriteRegister(_) => PacketType::WriteRegister(),
-            PacketData::ReadRegister(_) => PacketType::ReadRegister(),
-            PacketData::ReadRegisterResponse(_) => PacketType::ReadRegisterResponse(),
-            PacketData::WriteConfig(_) => PacketType::WriteConfig(),
-            PacketData::ReadConfig(_) => PacketType::ReadConfig(),
-            PacketData::ReadConfigResponse(_) => PacketType::ReadConfigResponse(),
-            PacketData::ReadProp(_) => PacketType::ReadProp(),
-            PacketData::ReadPropResponse(_) => PacketType::ReadPropResponse(),
-            PacketData::ObjectReportRequest() => PacketType::ObjectReportRequest(),
-            PacketData::ObjectReport(_) => PacketType::ObjectReport(),
-            PacketData::CombinedMarkersReport(_) => PacketType::CombinedMarkersReport(),
-            PacketData::PocMarkersReport(_) => PacketType::PocMarkersReport(),
-            PacketData::AccelReport(_) => PacketType::AccelReport(),
-            PacketData::ImpactReport(_) => PacketType::ImpactReport(),
-            PacketData::StreamUpdate(_) => PacketType::StreamUpdate(),
-            PacketData::FlashSettings() => PacketType::FlashSettings(),
-            PacketData::Ack() => PacketType::Ack(),
-            PacketData::WriteMode(_) => PacketType::WriteMode(),
-            PacketData::ReadVersion() => PacketType::ReadVersion(),
-            PacketData::ReadVersionResponse(_) => PacketType::ReadVersionResponse(),
-            PacketData::Vendor(n, _) => PacketType::Vendor(n),
-            PacketData::BatteryReport(_) => PacketType::BatteryReport(),
-            PacketData::SetDeviceName(_) => PacketType::SetDeviceName(),
-            PacketData::SetDeviceNameResponse(_) => PacketType::SetDeviceNameResponse(),
+    #[cfg(feature = "std")]
+    pub fn serialize_to_vec(&self, buf: &mut Vec<u8>) {
+        let len = self.serialized_len();
+        buf.reserve(len);
+        self.serialize(buf.spare_capacity_mut());
+        unsafe {
+            buf.set_len(buf.len() + len);
         }
     }
 
@@ -795,95 +1134,650 @@ impl Packet {
     }
 }
 
-impl PacketData {
-    pub fn read_register_response(self) -> Option<ReadRegisterResponse> {
-        match self {
-            PacketData::ReadRegisterResponse(x) => Some(x),
-            _ => None,
+/// Generates a consuming and a borrowing accessor per payload-carrying
+/// [`PacketData`] variant.
+macro_rules! accessors {
+    ($($variant:ident($payload:ty) => $name:ident, $as_name:ident;)*) => {
+        impl PacketData {
+            $(
+                pub fn $name(self) -> Option<$payload> {
+                    match self {
+                        PacketData::$variant(x) => Some(x),
+                        _ => None,
+                    }
+                }
+
+                pub fn $as_name(&self) -> Option<&$payload> {
+                    match self {
+                        PacketData::$variant(x) => Some(x),
+                        _ => None,
+                    }
+                }
+            )*
         }
-    }
+    };
+    (@unit $($variant:ident => $name:ident;)*) => {
+        impl PacketData {
+            $(
+                pub fn $name(&self) -> bool {
+                    matches!(self, PacketData::$variant())
+                }
+            )*
+        }
+    };
+}
+
+accessors! {
+    WriteRegister(WriteRegister) => write_register, as_write_register;
+    ReadRegister(Register) => read_register, as_read_register;
+    ReadRegisterResponse(ReadRegisterResponse) => read_register_response, as_read_register_response;
+    WriteConfig(GeneralConfig) => write_config, as_write_config;
+    ReadConfig(ConfigKind) => read_config, as_read_config;
+    ReadConfigResponse(GeneralConfig) => read_config_response, as_read_config_response;
+    ReadProp(PropKind) => read_prop, as_read_prop;
+    ReadPropResponse(Props) => read_prop_response, as_read_prop_response;
+    ObjectReport(ObjectReport) => object_report, as_object_report;
+    CombinedMarkersReport(CombinedMarkersReport) => combined_markers_report, as_combined_markers_report;
+    PocMarkersReport(PocMarkersReport) => poc_markers_report, as_poc_markers_report;
+    AccelReport(AccelReport) => accel_report, as_accel_report;
+    ImpactReport(ImpactReport) => impact_report, as_impact_report;
+    StreamUpdate(StreamUpdate) => stream_update, as_stream_update;
+    WriteMode(Mode) => write_mode, as_write_mode;
+    ReadVersionResponse(Version) => read_version_response, as_read_version_response;
+    BatteryReport(BatteryReport) => battery_report, as_battery_report;
+    SetDeviceName(heapless::String<32>) => set_device_name, as_set_device_name;
+    SetDeviceNameResponse(Result<(), ()>) => set_device_name_response, as_set_device_name_response;
+    ReadCapabilitiesResponse(Capabilities) => read_capabilities_response, as_read_capabilities_response;
+}
 
-    pub fn read_config_response(self) -> Option<GeneralConfig> {
+accessors! {
+    @unit
+    ObjectReportRequest => is_object_report_request;
+    FlashSettings => is_flash_settings;
+    Ack => is_ack;
+    ReadVersion => is_read_version;
+    ReadCapabilities => is_read_capabilities;
+}
+
+impl PacketData {
+    /// The packet type a device answers this request with, or `None` if it
+    /// sends no response.
+    pub fn expected_response(&self) -> Option<PacketType> {
         match self {
-            PacketData::ReadConfigResponse(x) => Some(x),
+            PacketData::ReadRegister(_) => Some(PacketType::ReadRegisterResponse()),
+            PacketData::ReadConfig(_) => Some(PacketType::ReadConfigResponse()),
+            PacketData::ReadProp(_) => Some(PacketType::ReadPropResponse()),
+            PacketData::ObjectReportRequest() => Some(PacketType::ObjectReport()),
+            PacketData::ReadVersion() => Some(PacketType::ReadVersionResponse()),
+            PacketData::SetDeviceName(_) => Some(PacketType::SetDeviceNameResponse()),
+            PacketData::ReadCapabilities() => Some(PacketType::ReadCapabilitiesResponse()),
+            PacketData::WriteRegister(_)
+            | PacketData::WriteConfig(_)
+            | PacketData::FlashSettings()
+            | PacketData::WriteMode(_) => Some(PacketType::Ack()),
             _ => None,
         }
     }
 
-    pub fn read_prop_response(self) -> Option<Props> {
+    /// The vendor packet id and its payload.
+    pub fn vendor(self) -> Option<(u8, VendorData)> {
         match self {
-            PacketData::ReadPropResponse(x) => Some(x),
+            PacketData::Vendor(n, x) => Some((n, x)),
             _ => None,
         }
     }
 
-    pub fn object_report(self) -> Option<ObjectReport> {
+    pub fn as_vendor(&self) -> Option<(u8, &VendorData)> {
         match self {
-            PacketData::ObjectReport(x) => Some(x),
+            PacketData::Vendor(n, x) => Some((*n, x)),
             _ => None,
         }
     }
+}
 
-    pub fn combined_markers_report(self) -> Option<CombinedMarkersReport> {
-        match self {
-            PacketData::CombinedMarkersReport(x) => Some(x),
-            _ => None,
+/// Generates `TryFrom<PacketData>` for each payload type, succeeding for the
+/// listed variant and handing the data back otherwise.
+macro_rules! payload_conversions {
+    ($($payload:ty => $variant:ident;)*) => {
+        $(
+            impl TryFrom<PacketData> for $payload {
+                type Error = PacketData;
+                fn try_from(data: PacketData) -> Result<Self, Self::Error> {
+                    match data {
+                        PacketData::$variant(x) => Ok(x),
+                        data => Err(data),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+payload_conversions! {
+    WriteRegister => WriteRegister;
+    Register => ReadRegister;
+    ReadRegisterResponse => ReadRegisterResponse;
+    // A `WriteConfig` is a request, not a config read back; take its payload
+    // with `write_config`.
+    GeneralConfig => ReadConfigResponse;
+    ConfigKind => ReadConfig;
+    PropKind => ReadProp;
+    Props => ReadPropResponse;
+    ObjectReport => ObjectReport;
+    CombinedMarkersReport => CombinedMarkersReport;
+    PocMarkersReport => PocMarkersReport;
+    AccelReport => AccelReport;
+    ImpactReport => ImpactReport;
+    StreamUpdate => StreamUpdate;
+    Mode => WriteMode;
+    Version => ReadVersionResponse;
+    BatteryReport => BatteryReport;
+    heapless::String<32> => SetDeviceName;
+    Result<(), ()> => SetDeviceNameResponse;
+    Capabilities => ReadCapabilitiesResponse;
+}
+
+impl TryFrom<PacketData> for VendorData {
+    type Error = PacketData;
+    fn try_from(data: PacketData) -> Result<Self, Self::Error> {
+        match data {
+            PacketData::Vendor(_, x) => Ok(x),
+            data => Err(data),
         }
     }
+}
 
-    pub fn poc_markers_report(self) -> Option<PocMarkersReport> {
-        match self {
-            PacketData::PocMarkersReport(x) => Some(x),
-            _ => None,
-        }
+impl Parse for WriteRegister {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let [port, bank, address, data] = take(bytes)?;
+        Ok(Self {
+            port: port.try_into()?,
+            bank,
+            address,
+            data,
+        })
     }
+}
 
-    pub fn accel_report(self) -> Option<AccelReport> {
-        match self {
-            PacketData::AccelReport(x) => Some(x),
-            _ => None,
-        }
+impl Serialize for WriteRegister {
+    const SIZE: usize = 4;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &[self.port as u8, self.bank, self.address, self.data]);
     }
+}
 
-    pub fn impact_report(self) -> Option<ImpactReport> {
-        match self {
-            PacketData::ImpactReport(x) => Some(x),
-            _ => None,
-        }
+impl Parse for Register {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let [port, bank, address, _] = take(bytes)?;
+        Ok(Self {
+            port: port.try_into()?,
+            bank,
+            address,
+        })
     }
+}
 
-    pub fn battery_report(self) -> Option<BatteryReport> {
-        match self {
-            PacketData::BatteryReport(x) => Some(x),
-            _ => None,
-        }
+impl Serialize for Register {
+    const SIZE: usize = 4;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &[self.port as u8, self.bank, self.address, 0]);
     }
 }
 
-impl Parse for MotData {
+impl Parse for ReadRegisterResponse {
     fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
-        let mot_data = MotData {
-            area: bytes[0] as u16 | ((bytes[1] as u16) << 8),
-            cx: bytes[2] as u16 | ((bytes[3] & 0x0f) as u16) << 8,
-            cy: bytes[4] as u16 | ((bytes[5] & 0x0f) as u16) << 8,
-            avg_brightness: bytes[6],
-            max_brightness: bytes[7],
-            radius: bytes[8] & 0x0f,
-            range: bytes[8] >> 4,
-            boundary_left: bytes[9] & 0x7f,
-            boundary_right: bytes[10] & 0x7f,
-            boundary_up: bytes[11] & 0x7f,
-            boundary_down: bytes[12] & 0x7f,
-            aspect_ratio: bytes[13],
-            vx: bytes[14],
-            vy: bytes[15],
-        };
-        *bytes = &bytes[16..];
-        Ok(mot_data)
+        let [bank, address, data, _] = take(bytes)?;
+        Ok(Self {
+            bank,
+            address,
+            data,
+        })
     }
 }
 
-impl Serialize for MotData {
+impl Serialize for ReadRegisterResponse {
+    const SIZE: usize = 4;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &[self.bank, self.address, self.data, 0]);
+    }
+}
+
+fn parse_f32s<const N: usize>(bytes: &mut &[u8]) -> Result<[f32; N], Error> {
+    let mut out = [0.0; N];
+    for v in &mut out {
+        *v = f32::from_le_bytes(take(bytes)?);
+    }
+    Ok(out)
+}
+
+fn push_f32s(buf: &mut &mut [MaybeUninit<u8>], data: &[f32]) {
+    for v in data {
+        push(buf, &v.to_le_bytes());
+    }
+}
+
+/// Runs `f` and zero-fills whatever it left of the next `size` bytes.
+fn push_padded(
+    buf: &mut &mut [MaybeUninit<u8>],
+    size: usize,
+    f: impl FnOnce(&mut &mut [MaybeUninit<u8>]),
+) {
+    let start = buf.len();
+    f(buf);
+    for _ in start - buf.len()..size {
+        push(buf, &[0]);
+    }
+}
+
+impl Parse for AccelConfig {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let accel_odr = u16::from_le_bytes(take(bytes)?);
+        let [b_x, b_y, b_z, s_x, s_y, s_z] = parse_f32s(bytes)?;
+        Ok(Self {
+            accel_odr,
+            b_x,
+            b_y,
+            b_z,
+            s_x,
+            s_y,
+            s_z,
+        })
+    }
+}
+
+impl Serialize for AccelConfig {
+    const SIZE: usize = 26;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &self.accel_odr.to_le_bytes());
+        push_f32s(
+            buf,
+            &[self.b_x, self.b_y, self.b_z, self.s_x, self.s_y, self.s_z],
+        );
+    }
+}
+
+impl Parse for GyroConfig {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let [b_x, b_y, b_z] = parse_f32s(bytes)?;
+        Ok(Self { b_x, b_y, b_z })
+    }
+}
+
+impl Serialize for GyroConfig {
+    const SIZE: usize = 12;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push_f32s(buf, &[self.b_x, self.b_y, self.b_z]);
+    }
+}
+
+impl Parse for ConfigKind {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let [kind, _] = take(bytes)?;
+        kind.try_into()
+    }
+}
+
+impl Serialize for ConfigKind {
+    const SIZE: usize = 2;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &[*self as u8, 0]);
+    }
+}
+
+/// `[kind, 0]` followed by the payload for `kind`, padded to 56 bytes.
+impl Parse for GeneralConfig {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let kind = ConfigKind::parse(bytes)?;
+        let payload = &mut &take::<56>(bytes)?[..];
+        Ok(match kind {
+            ConfigKind::ImpactThreshold => Self::ImpactThreshold(take::<1>(payload)?[0]),
+            ConfigKind::SuppressMs => Self::SuppressMs(take::<1>(payload)?[0]),
+            ConfigKind::AccelConfig => Self::AccelConfig(AccelConfig::parse(payload)?),
+            ConfigKind::GyroConfig => Self::GyroConfig(GyroConfig::parse(payload)?),
+            ConfigKind::CameraModelNf => {
+                Self::CameraModelNf(wire::CameraCalibrationParams::parse(payload)?.into())
+            }
+            ConfigKind::CameraModelWf => {
+                Self::CameraModelWf(wire::CameraCalibrationParams::parse(payload)?.into())
+            }
+            ConfigKind::StereoIso => {
+                Self::StereoIso(wire::StereoCalibrationParams::parse(payload)?.into())
+            }
+        })
+    }
+}
+
+impl GeneralConfig {
+    pub fn kind(&self) -> ConfigKind {
+        match self {
+            Self::ImpactThreshold(_) => ConfigKind::ImpactThreshold,
+            Self::SuppressMs(_) => ConfigKind::SuppressMs,
+            Self::AccelConfig(_) => ConfigKind::AccelConfig,
+            Self::GyroConfig(_) => ConfigKind::GyroConfig,
+            Self::CameraModelNf(_) => ConfigKind::CameraModelNf,
+            Self::CameraModelWf(_) => ConfigKind::CameraModelWf,
+            Self::StereoIso(_) => ConfigKind::StereoIso,
+        }
+    }
+}
+
+impl Serialize for GeneralConfig {
+    const SIZE: usize = 58;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        self.kind().serialize(buf);
+        push_padded(buf, 56, |buf| match self {
+            Self::ImpactThreshold(x) | Self::SuppressMs(x) => push(buf, &[*x]),
+            Self::AccelConfig(x) => x.serialize(buf),
+            Self::GyroConfig(x) => x.serialize(buf),
+            Self::CameraModelNf(x) | Self::CameraModelWf(x) => {
+                wire::CameraCalibrationParams::from(x.clone()).serialize(buf)
+            }
+            Self::StereoIso(x) => wire::StereoCalibrationParams::from(*x).serialize(buf),
+        });
+    }
+}
+
+// CBOR goes through `wire::GeneralConfig`, like serde does. `PacketData` keeps
+// the serde-based `serde_cbor_with` encoding for config packets, which deployed
+// firmware expects.
+#[cfg(feature = "minicbor")]
+impl<C> Encode<C> for GeneralConfig {
+    fn encode<W: minicbor::encode::Write>(
+        &self,
+        e: &mut minicbor::Encoder<W>,
+        ctx: &mut C,
+    ) -> Result<(), minicbor::encode::Error<W::Error>> {
+        wire::GeneralConfig::from(self.clone()).encode(e, ctx)
+    }
+}
+
+#[cfg(feature = "minicbor")]
+impl<'b, C> Decode<'b, C> for GeneralConfig {
+    fn decode(d: &mut minicbor::Decoder<'b>, ctx: &mut C) -> Result<Self, minicbor::decode::Error> {
+        wire::GeneralConfig::decode(d, ctx).map(Into::into)
+    }
+}
+
+#[cfg(feature = "minicbor")]
+impl<C> CborLen<C> for GeneralConfig {
+    fn cbor_len(&self, ctx: &mut C) -> usize {
+        wire::GeneralConfig::from(self.clone()).cbor_len(ctx)
+    }
+}
+
+impl Parse for PropKind {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let [kind, _] = take(bytes)?;
+        kind.try_into()
+    }
+}
+
+impl Serialize for PropKind {
+    const SIZE: usize = 2;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &[*self as u8, 0]);
+    }
+}
+
+/// `[len, utf8[32], 0]`
+impl Parse for heapless::String<32> {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let [len, data @ .., _]: [u8; 34] = take(bytes)?;
+        let data = data
+            .get(..usize::from(len))
+            .ok_or(Error::InvalidBitPattern)?;
+        let s = core::str::from_utf8(data).map_err(|_| Error::InvalidBitPattern)?;
+        s.try_into().map_err(|_| Error::InvalidBitPattern)
+    }
+}
+
+impl Serialize for heapless::String<32> {
+    const SIZE: usize = 34;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &[self.len() as u8]);
+        push_padded(buf, 33, |buf| push(buf, self.as_bytes()));
+    }
+}
+
+/// `[kind, 0]` followed by the payload for `kind`, padded to 34 bytes.
+impl Parse for Props {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let kind = PropKind::parse(bytes)?;
+        let payload = &mut &take::<34>(bytes)?[..];
+        Ok(match kind {
+            PropKind::Uuid => Self::Uuid(take(payload)?),
+            PropKind::ProductId => Self::ProductId(u16::from_le_bytes(take(payload)?)),
+            PropKind::Name => Self::Name(Parse::parse(payload)?),
+            PropKind::Version => Self::Version(Version::parse(payload)?),
+        })
+    }
+}
+
+impl Props {
+    pub fn kind(&self) -> PropKind {
+        match self {
+            Self::Uuid(_) => PropKind::Uuid,
+            Self::ProductId(_) => PropKind::ProductId,
+            Self::Name(_) => PropKind::Name,
+            Self::Version(_) => PropKind::Version,
+        }
+    }
+}
+
+impl Serialize for Props {
+    const SIZE: usize = 36;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        self.kind().serialize(buf);
+        push_padded(buf, 34, |buf| match self {
+            Self::Uuid(x) => push(buf, x),
+            Self::ProductId(x) => push(buf, &x.to_le_bytes()),
+            Self::Name(x) => x.serialize(buf),
+            Self::Version(x) => x.serialize(buf),
+        });
+    }
+}
+
+impl Parse for Version {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let mut semver = [0; 6];
+        for v in &mut semver {
+            *v = u16::from_le_bytes(take(bytes)?);
+        }
+        let [p0, p1, p2, f0, f1, f2] = semver;
+        Ok(Self {
+            protocol_semver: [p0, p1, p2],
+            firmware_semver: [f0, f1, f2],
+        })
+    }
+}
+
+impl Serialize for Version {
+    const SIZE: usize = 12;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        for v in self.protocol_semver.iter().chain(&self.firmware_semver) {
+            push(buf, &v.to_le_bytes());
+        }
+    }
+}
+
+impl Parse for PacketTypeSet {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let mut bits = [0; 4];
+        for word in &mut bits {
+            *word = u64::from_le_bytes(take(bytes)?);
+        }
+        Ok(Self { bits })
+    }
+}
+
+impl Serialize for PacketTypeSet {
+    const SIZE: usize = 32;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        for word in self.bits {
+            push(buf, &word.to_le_bytes());
+        }
+    }
+}
+
+impl Parse for Capabilities {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let packet_types = PacketTypeSet::parse(bytes)?;
+        let streams = PacketTypeSet::parse(bytes)?;
+        let config_kinds = u32::from_le_bytes(take(bytes)?);
+        let [transports, control_endpoint] = take(bytes)?;
+        let control_endpoint = match control_endpoint {
+            0 => false,
+            1 => true,
+            _ => return Err(Error::InvalidBitPattern),
+        };
+        Ok(Self {
+            packet_types,
+            streams,
+            config_kinds,
+            transports,
+            control_endpoint,
+        })
+    }
+}
+
+impl Serialize for Capabilities {
+    const SIZE: usize = 70;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        self.packet_types.serialize(buf);
+        self.streams.serialize(buf);
+        push(buf, &self.config_kinds.to_le_bytes());
+        push(buf, &[self.transports, self.control_endpoint as u8]);
+    }
+}
+
+impl Parse for Mode {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let [mode, _] = take(bytes)?;
+        mode.try_into()
+    }
+}
+
+impl Serialize for Mode {
+    const SIZE: usize = 2;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &[*self as u8, 0]);
+    }
+}
+
+/// `[len, data[98], 0]`
+impl Parse for VendorData {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let [len, data @ .., _]: [u8; 100] = take(bytes)?;
+        if usize::from(len) > data.len() {
+            return Err(Error::InvalidBitPattern);
+        }
+        Ok(Self { len, data })
+    }
+}
+
+impl Serialize for VendorData {
+    const SIZE: usize = 100;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &[self.len]);
+        push(buf, &self.data);
+        push(buf, &[0]);
+    }
+}
+
+impl Parse for ImpactReport {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            timestamp: u32::from_le_bytes(take(bytes)?),
+        })
+    }
+}
+
+impl Serialize for ImpactReport {
+    const SIZE: usize = 4;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &self.timestamp.to_le_bytes());
+    }
+}
+
+impl Parse for AccelReport {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        wire::AccelReport::parse(bytes).map(Into::into)
+    }
+}
+
+impl Serialize for AccelReport {
+    const SIZE: usize = wire::AccelReport::SIZE;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        wire::AccelReport::from(*self).serialize(buf);
+    }
+}
+
+impl Parse for BatteryReport {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let [percent, charging] = take(bytes)?;
+        let charging = match charging {
+            0 => false,
+            1 => true,
+            _ => return Err(Error::InvalidBitPattern),
+        };
+        Ok(Self { percent, charging })
+    }
+}
+
+impl Serialize for BatteryReport {
+    const SIZE: usize = 2;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &[self.percent, self.charging as u8]);
+    }
+}
+
+/// `[0, 0]` for `Ok`, `[1, 0]` for `Err`
+impl Parse for Result<(), ()> {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        match take(bytes)? {
+            [0, _] => Ok(Ok(())),
+            [1, _] => Ok(Err(())),
+            _ => Err(Error::InvalidBitPattern),
+        }
+    }
+}
+
+impl Serialize for Result<(), ()> {
+    const SIZE: usize = 2;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &[self.is_err() as u8, 0]);
+    }
+}
+
+/// Payload of packets that carry no data
+impl Serialize for () {
+    const SIZE: usize = 0;
+    fn serialize(&self, _buf: &mut &mut [MaybeUninit<u8>]) {}
+}
+
+impl Parse for MotData {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let b: [u8; 16] = take(bytes)?;
+        Ok(MotData {
+            area: b[0] as u16 | ((b[1] as u16) << 8),
+            cx: b[2] as u16 | ((b[3] & 0x0f) as u16) << 8,
+            cy: b[4] as u16 | ((b[5] & 0x0f) as u16) << 8,
+            avg_brightness: b[6],
+            max_brightness: b[7],
+            radius: b[8] & 0x0f,
+            range: b[8] >> 4,
+            boundary_left: b[9] & 0x7f,
+            boundary_right: b[10] & 0x7f,
+            boundary_up: b[11] & 0x7f,
+            boundary_down: b[12] & 0x7f,
+            aspect_ratio: b[13],
+            vx: b[14],
+            vy: b[15],
+        })
+    }
+}
+
+impl Serialize for MotData {
     const SIZE: usize = 16;
     fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
         let data = [
@@ -895,7 +1789,7 @@ impl Serialize for MotData {
             (self.cy >> 8) as u8,
             self.avg_brightness,
             self.max_brightness,
-            self.radius | self.range << 4,
+            (self.radius & 0x0f) | (self.range << 4),
             self.boundary_left,
             self.boundary_right,
             self.boundary_up,
@@ -904,30 +1798,29 @@ impl Serialize for MotData {
             self.vx,
             self.vy,
         ];
-        let ptr = <[_]>::as_mut_ptr(buf) as *mut u8;
-        unsafe {
-            ptr.copy_from_nonoverlapping(data.as_ptr(), data.len());
-        }
+        push(buf, &data);
     }
 }
 
 impl Parse for ObjectReport {
     fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
         use Error as E;
-        let timestamp = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
-        *bytes = &bytes[4..];
-        let data = &mut &bytes[..512];
-        *bytes = &bytes[512..];
-        let [_format, _, ..] = **bytes else {
+        if bytes.len() < Self::SIZE {
             return Err(E::UnexpectedEof {
                 packet_type: Some(PacketType::ObjectReport()),
             });
-        };
-        *bytes = &bytes[2..];
+        }
+        let timestamp = u32::from_le_bytes(take(bytes)?);
+        let mut mot_data_nf = [MotData::default(); 16];
+        let mut mot_data_wf = [MotData::default(); 16];
+        for mot_data in mot_data_nf.iter_mut().chain(&mut mot_data_wf) {
+            *mot_data = MotData::parse(bytes)?;
+        }
+        let [_format, _] = take(bytes)?;
         Ok(Self {
             timestamp,
-            mot_data_nf: [(); 16].map(|_| MotData::parse(data).expect("MotData parse error")),
-            mot_data_wf: [(); 16].map(|_| MotData::parse(data).expect("MotData parse error")),
+            mot_data_nf,
+            mot_data_wf,
         })
     }
 }
@@ -990,6 +1883,33 @@ impl Serialize for CombinedMarkersReport {
     }
 }
 
+impl Parse for PocMarkersReport {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let mut points = [Point2::new(0, 0); 16];
+        for p in &mut points {
+            // x, y is 12 bits each
+            let [b0, b1, b2] = take(bytes)?;
+            let x = u16::from_le_bytes([b0, b1 & 0x0f]);
+            let y = (b1 >> 4) as u16 | ((b2 as u16) << 4);
+            *p = Point2::new(x, y);
+        }
+        Ok(Self { points })
+    }
+}
+
+impl Serialize for PocMarkersReport {
+    const SIZE: usize = 48;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        for p in &self.points {
+            let (x, y) = (p.x, p.y);
+            let byte0 = x & 0xff;
+            let byte1 = ((x >> 8) & 0x0f) | ((y & 0x0f) << 4);
+            let byte2 = y >> 4;
+            push(buf, &[byte0 as u8, byte1 as u8, byte2 as u8]);
+        }
+    }
+}
+
 #[cfg(feature = "pyo3")]
 #[pyo3::pymethods]
 impl CombinedMarkersReport {
@@ -1030,14 +1950,20 @@ impl AccelReport {
     }
 }
 
-impl StreamUpdate {
-    pub fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
-        let stream_update = StreamUpdate {
-            packet_id: bytes[0].try_into()?,
-            action: bytes[1].try_into()?,
-        };
-        *bytes = &bytes[2..];
-        Ok(stream_update)
+impl Parse for StreamUpdate {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let [packet_id, action] = take(bytes)?;
+        Ok(StreamUpdate {
+            packet_id: packet_id.try_into()?,
+            action: action.try_into()?,
+        })
+    }
+}
+
+impl Serialize for StreamUpdate {
+    const SIZE: usize = 2;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &[self.packet_id.into(), self.action as u8]);
     }
 }
 
@@ -1075,6 +2001,47 @@ mod heapless_str32_cbor {
     }
 }
 
+#[cfg(feature = "minicbor")]
+mod heapless_vec_cbor {
+    use minicbor::{
+        CborLen, Decode, Decoder, Encode, Encoder, decode::Error, encode::Error as EncodeError,
+        encode::Write,
+    };
+
+    pub fn encode<Ctx, W: Write, T: Encode<Ctx>, const N: usize>(
+        v: &heapless::Vec<T, N>,
+        e: &mut Encoder<W>,
+        ctx: &mut Ctx,
+    ) -> Result<(), EncodeError<W::Error>> {
+        v.as_slice().encode(e, ctx)
+    }
+
+    pub fn decode<'b, Ctx, T: Decode<'b, Ctx>, const N: usize>(
+        d: &mut Decoder<'b>,
+        ctx: &mut Ctx,
+    ) -> Result<heapless::Vec<T, N>, Error> {
+        let p = d.position();
+        if d.array()?.is_some_and(|len| len > N as u64) {
+            return Err(Error::message("array longer than heapless::Vec capacity").at(p));
+        }
+        d.set_position(p);
+        let mut v = heapless::Vec::new();
+        for x in d.array_iter_with(ctx)? {
+            if v.push(x?).is_err() {
+                return Err(Error::message("array longer than heapless::Vec capacity").at(p));
+            }
+        }
+        Ok(v)
+    }
+
+    pub fn cbor_len<Ctx, T: CborLen<Ctx>, const N: usize>(
+        v: &heapless::Vec<T, N>,
+        ctx: &mut Ctx,
+    ) -> usize {
+        v.as_slice().cbor_len(ctx)
+    }
+}
+
 #[cfg(feature = "minicbor")]
 mod serde_cbor_with {
     use minicbor::{Decoder, Encoder};
@@ -1136,6 +2103,31 @@ mod serde_cbor_with {
     }
 }
 
+#[cfg(all(feature = "std", feature = "minicbor"))]
+mod vec_cbor {
+    use core::convert::Infallible;
+    use std::vec::Vec;
+
+    struct VecWriter<'a>(&'a mut Vec<u8>);
+
+    impl minicbor::encode::Write for VecWriter<'_> {
+        type Error = Infallible;
+
+        fn write_all(&mut self, buf: &[u8]) -> Result<(), Infallible> {
+            self.0.extend_from_slice(buf);
+            Ok(())
+        }
+    }
+
+    /// Appends the encoding of `v` to `buf`.
+    pub fn encode<T: minicbor::Encode<()>>(
+        v: &T,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), minicbor::encode::Error<Infallible>> {
+        minicbor::encode(v, VecWriter(buf))
+    }
+}
+
 #[cfg_attr(feature = "pyo3", pyo3::pyclass)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -1159,7 +2151,373 @@ impl ProductId {
         }
     }
 
+    /// Hardcoded per product, so it is wrong for firmware that adds or drops
+    /// the endpoint.
+    #[deprecated(
+        note = "ask the device with `ReadCapabilities` and use `Capabilities::supports_control_endpoint`"
+    )]
     pub fn supports_control_endpoint(&self) -> bool {
         matches!(self, Self::AtsLite | Self::Mux)
     }
 }
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn serialized(data: PacketData) -> Vec<u8> {
+        let mut buf = Vec::new();
+        Packet { data, id: 7 }.serialize_to_vec(&mut buf);
+        buf
+    }
+
+    fn round_trip(data: PacketData) -> PacketData {
+        let buf = serialized(data);
+        let bytes = &mut &buf[..];
+        let pkt = Packet::parse(bytes).unwrap();
+        assert!(bytes.is_empty());
+        assert_eq!(pkt.id, 7);
+        pkt.data
+    }
+
+    fn mot_data(i: usize) -> MotData {
+        let i = i as u8;
+        MotData {
+            area: 0x100 + u16::from(i),
+            cx: 0xfff - u16::from(i),
+            cy: 0x800 + u16::from(i),
+            avg_brightness: i,
+            max_brightness: 0xff - i,
+            range: i & 0x0f,
+            radius: 0x0f - (i & 0x0f),
+            boundary_left: i,
+            boundary_right: 0x7f - i,
+            boundary_up: 0x40 + i,
+            boundary_down: 0x7f,
+            aspect_ratio: i * 3,
+            vx: i * 5,
+            vy: 0xff - i * 7,
+        }
+    }
+
+    fn points(offset: u16) -> [Point2<u16>; 16] {
+        core::array::from_fn(|i| Point2::new(offset + i as u16 * 200, 0xfff - i as u16))
+    }
+
+    /// A packet of type `ty` with its payload filled in, `None` for the
+    /// marker types no packet has.
+    pub(crate) fn sample(ty: PacketType) -> Option<PacketData> {
+        use PacketType as T;
+        Some(match ty {
+            T::WriteRegister() => PacketData::WriteRegister(WriteRegister {
+                port: Port::Wf,
+                bank: 1,
+                address: 0x22,
+                data: 0x33,
+            }),
+            T::ReadRegister() => PacketData::ReadRegister(Register {
+                port: Port::Wf,
+                bank: 1,
+                address: 0x22,
+            }),
+            T::ReadRegisterResponse() => PacketData::ReadRegisterResponse(ReadRegisterResponse {
+                bank: 1,
+                address: 0x22,
+                data: 0x33,
+            }),
+            T::WriteConfig() => {
+                PacketData::WriteConfig(GeneralConfig::AccelConfig(AccelConfig::default()))
+            }
+            T::ReadConfig() => PacketData::ReadConfig(ConfigKind::GyroConfig),
+            T::ReadConfigResponse() => {
+                PacketData::ReadConfigResponse(GeneralConfig::GyroConfig(GyroConfig {
+                    b_x: 0.5,
+                    b_y: -1.0,
+                    b_z: 2.0,
+                }))
+            }
+            T::ReadProp() => PacketData::ReadProp(PropKind::Name),
+            T::ReadPropResponse() => {
+                PacketData::ReadPropResponse(Props::Name("dongle".try_into().unwrap()))
+            }
+            T::ObjectReportRequest() => PacketData::ObjectReportRequest(),
+            T::ObjectReport() => PacketData::ObjectReport(ObjectReport {
+                timestamp: 1,
+                mot_data_nf: core::array::from_fn(mot_data),
+                mot_data_wf: core::array::from_fn(|i| mot_data(i + 16)),
+            }),
+            T::CombinedMarkersReport() => {
+                PacketData::CombinedMarkersReport(CombinedMarkersReport {
+                    nf_points: points(0),
+                    wf_points: points(7),
+                })
+            }
+            T::PocMarkersReport() => {
+                PacketData::PocMarkersReport(PocMarkersReport { points: points(3) })
+            }
+            T::AccelReport() => PacketData::AccelReport(AccelReport {
+                timestamp: 2,
+                accel: Vector3::new(1.0, -2.0, 9.5),
+                gyro: Vector3::new(0.25, 0.0, -0.5),
+            }),
+            T::ImpactReport() => PacketData::ImpactReport(ImpactReport { timestamp: 3 }),
+            T::StreamUpdate() => PacketData::StreamUpdate(StreamUpdate {
+                packet_id: PacketType::AccelReport(),
+                action: StreamUpdateAction::Disable,
+            }),
+            T::FlashSettings() => PacketData::FlashSettings(),
+            T::Ack() => PacketData::Ack(),
+            T::WriteMode() => PacketData::WriteMode(Mode::Image),
+            T::ReadVersion() => PacketData::ReadVersion(),
+            T::ReadVersionResponse() => PacketData::ReadVersionResponse(Version::new([1, 2, 3])),
+            T::BatteryReport() => PacketData::BatteryReport(BatteryReport {
+                percent: 80,
+                charging: true,
+            }),
+            T::SetDeviceName() => PacketData::SetDeviceName("gun 1".try_into().unwrap()),
+            T::SetDeviceNameResponse() => PacketData::SetDeviceNameResponse(Err(())),
+            T::ReadCapabilities() => PacketData::ReadCapabilities(),
+            T::ReadCapabilitiesResponse() => PacketData::ReadCapabilitiesResponse(Capabilities {
+                packet_types: PacketTypeSet {
+                    bits: [0b1011, 1, 0, 1 << 5],
+                },
+                streams: PacketTypeSet {
+                    bits: [1 << 9, 0, 1 << 63, 0],
+                },
+                config_kinds: 0x7f,
+                transports: 0b11,
+                control_endpoint: true,
+            }),
+            T::Vendor(n) => {
+                let mut data = [0; 98];
+                data[..3].copy_from_slice(&[1, 2, 3]);
+                PacketData::Vendor(n, VendorData { len: 3, data })
+            }
+            T::End() | T::VendorStart() | T::VendorEnd() => return None,
+        })
+    }
+
+    /// One sample of every packet type, vendor ids included.
+    pub(crate) fn samples() -> impl Iterator<Item = PacketData> {
+        (0..=u8::MAX).filter_map(|id| sample(PacketType::try_from(id).ok()?))
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        for data in samples() {
+            let ty = data.ty();
+            let bytes = serialized(data);
+            for len in 0..bytes.len() {
+                let r = Packet::parse(&mut &bytes[..len]);
+                assert!(r.is_err(), "{ty:?} cut to {len} bytes parsed");
+            }
+            // A header that agrees with the short payload, which reaches the
+            // payload's own bounds checks (ObjectReport's included).
+            let payload = &bytes[4..];
+            for len in 0..payload.len() {
+                let r = Packet::parse_data(ty, &mut &payload[..len]);
+                assert!(r.is_err(), "{ty:?} payload cut to {len} bytes parsed");
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_input_is_handled() {
+        for data in samples() {
+            let ty = data.ty();
+            let mut bytes = serialized(data);
+            let words = u16::from_le_bytes([bytes[0], bytes[1]]);
+            bytes.extend([0xa5; 16]);
+
+            // Trailing payload bytes the type doesn't use are skipped.
+            bytes[..2].copy_from_slice(&(words + 8).to_le_bytes());
+            let rest = &mut &bytes[..];
+            assert!(Packet::parse(rest).is_ok(), "{ty:?} with a long payload");
+            assert!(rest.is_empty());
+
+            // A length past the end of the input is not.
+            for words in [words + 9, u16::MAX] {
+                bytes[..2].copy_from_slice(&words.to_le_bytes());
+                let r = Packet::parse(&mut &bytes[..]);
+                assert!(r.is_err(), "{ty:?} claiming {words} words parsed");
+            }
+        }
+    }
+
+    #[test]
+    fn every_id_round_trips() {
+        let mut header = serialized(PacketData::ReadVersion());
+        for id in 0..=u8::MAX {
+            let sample = match PacketType::try_from(id) {
+                Ok(ty) => {
+                    assert_eq!(u8::from(ty), id);
+                    sample(ty)
+                }
+                Err(e) => {
+                    assert!(matches!(e, Error::UnrecognizedPacketId(n) if n == id));
+                    None
+                }
+            };
+            let Some(data) = sample else {
+                header[2] = id;
+                let r = Packet::parse(&mut &header[..]);
+                assert!(
+                    matches!(r, Err(Error::UnrecognizedPacketId(n)) if n == id),
+                    "{id:#x} parsed"
+                );
+                continue;
+            };
+            let bytes = serialized(data);
+            assert_eq!(bytes[2], id);
+            let pkt = Packet::parse(&mut &bytes[..]).unwrap();
+            assert_eq!(u8::from(pkt.data.ty()), id);
+            assert_eq!(
+                serialized(pkt.data),
+                bytes,
+                "{id:#x} changed in a round trip"
+            );
+        }
+    }
+
+    #[test]
+    fn packet_type_sets_cover_every_id() {
+        let mut set = PacketTypeSet::empty();
+        for id in [0x00, 0x19, 0x81, 0xc0, 0xfe] {
+            let ty = PacketType::try_from(id).unwrap();
+            assert!(!set.contains(ty));
+            set.insert(ty);
+            assert!(set.contains(ty));
+        }
+        assert_eq!(set.bits, [1 | 1 << 25, 0, 1 << 1, 1 | 1 << 62]);
+        set.remove(PacketType::Vendor(0x81));
+        assert!(!set.contains(PacketType::Vendor(0x81)));
+        assert!(set.contains(PacketType::Vendor(0xfe)));
+    }
+
+    /// Each wire id's CBOR index as a `PacketData` and as a `PacketType`,
+    /// which deployed peers rely on.
+    #[cfg(feature = "minicbor")]
+    const CBOR_INDICES: [(u8, Option<u32>, u32); 29] = [
+        (0x00, Some(0), 0),
+        (0x01, Some(1), 1),
+        (0x02, Some(2), 2),
+        (0x03, Some(3), 3),
+        (0x04, Some(4), 4),
+        (0x05, Some(5), 5),
+        (0x06, Some(6), 6),
+        (0x07, Some(7), 7),
+        (0x08, Some(8), 8),
+        (0x09, Some(9), 9),
+        (0x0a, Some(10), 10),
+        (0x0b, Some(12), 11),
+        (0x0c, Some(13), 12),
+        (0x0d, Some(14), 13),
+        (0x0e, Some(15), 14),
+        (0x0f, Some(16), 15),
+        (0x10, Some(11), 16),
+        (0x11, Some(17), 17),
+        (0x12, Some(18), 18),
+        (0x13, Some(19), 19),
+        (0x14, None, 20),
+        (0x80, None, 21),
+        (0x81, Some(20), 22),
+        (0xff, None, 23),
+        (0x15, Some(21), 24),
+        (0x16, Some(22), 25),
+        (0x17, Some(23), 26),
+        (0x18, Some(24), 27),
+        (0x19, Some(25), 28),
+    ];
+
+    #[cfg(feature = "minicbor")]
+    #[test]
+    fn cbor_indices_are_unchanged() {
+        fn index(v: &impl Encode<()>) -> u32 {
+            let mut buf = Vec::new();
+            vec_cbor::encode(v, &mut buf).unwrap();
+            let mut d = minicbor::Decoder::new(&buf);
+            d.array().unwrap();
+            d.u32().unwrap()
+        }
+        for (id, data_index, type_index) in CBOR_INDICES {
+            let ty = PacketType::try_from(id).unwrap();
+            assert_eq!(index(&ty), type_index, "{ty:?}");
+            assert_eq!(sample(ty).map(|data| index(&data)), data_index, "{ty:?}");
+        }
+    }
+
+    #[test]
+    fn garbage_input_does_not_panic() {
+        // xorshift64, so failures reproduce
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        };
+        let mut bytes = [0; Packet::MAX_SERIALIZED_LEN + 8];
+        for id in 0..=u8::MAX {
+            for _ in 0..64 {
+                bytes.fill_with(&mut next);
+                bytes[2] = id;
+                bytes[1] &= 0x01;
+                let _ = Packet::parse(&mut &bytes[..]);
+                if let Ok(ty) = PacketType::try_from(id) {
+                    let len = usize::from(next()) * bytes.len() / 255;
+                    let _ = Packet::parse_data(ty, &mut &bytes[..len]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn object_report_round_trips() {
+        let report = ObjectReport {
+            timestamp: 0x1234_5678,
+            mot_data_nf: core::array::from_fn(mot_data),
+            mot_data_wf: core::array::from_fn(|i| mot_data(i + 16)),
+        };
+        let data = round_trip(PacketData::ObjectReport(report));
+        assert_eq!(data.object_report(), Some(report));
+    }
+
+    #[test]
+    fn serialize_truncates_to_wire_width() {
+        let wide = MotData {
+            cx: 0x1abc,
+            cy: 0xffff,
+            range: 0x1f,
+            radius: 0x3e,
+            boundary_left: 0x80,
+            boundary_right: 0xff,
+            boundary_up: 0x81,
+            boundary_down: 0xc0,
+            ..MotData::default()
+        };
+        let report = ObjectReport {
+            mot_data_nf: [wide; 16],
+            ..ObjectReport::default()
+        };
+        let data = round_trip(PacketData::ObjectReport(report));
+        let narrow = MotData {
+            cx: 0xabc,
+            cy: 0xfff,
+            range: 0x0f,
+            radius: 0x0e,
+            boundary_left: 0x00,
+            boundary_right: 0x7f,
+            boundary_up: 0x01,
+            boundary_down: 0x40,
+            ..MotData::default()
+        };
+        assert_eq!(data.object_report().unwrap().mot_data_nf, [narrow; 16]);
+
+        let points = PocMarkersReport {
+            points: [Point2::new(0x1234, 0xfffe); 16],
+        };
+        let data = round_trip(PacketData::PocMarkersReport(points));
+        let expected = [Point2::new(0x234, 0xffe); 16];
+        assert_eq!(data.poc_markers_report().unwrap().points, expected);
+    }
+}
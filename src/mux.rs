@@ -1,53 +1,383 @@
 use heapless::Vec;
 
-use crate::Packet;
+use crate::{
+    Capabilities, Error, Packet, PacketType, PacketTypeSet, control::device::TransportMode,
+    handshake,
+};
 
+#[cfg(feature = "std")]
+pub mod emulator;
+
+/// Default device capacity of [`MuxMsg`] and
+/// [`UsbMuxCtrlMsg`](crate::control::usb_mux::UsbMuxCtrlMsg).
 pub const MAX_DEVICES: usize = 3;
 pub type Uuid = [u8; 6];
 
 const SEMVER: [u16; 3] = [0, 1, 0];
 
-#[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
+/// [`MuxMsgN`] with the default capacity of [`MAX_DEVICES`].
+pub type MuxMsg = MuxMsgN<MAX_DEVICES>;
+
+/// The Python class for [`MuxMsg`]. pyo3 classes cannot be generic, so
+/// Python only sees the default capacity.
+#[cfg(feature = "pyo3")]
+#[pyo3::pyclass(name = "MuxMsg")]
+#[derive(Clone, Debug)]
+pub struct PyMuxMsg(pub MuxMsg);
+
+#[cfg(feature = "pyo3")]
+impl From<MuxMsg> for PyMuxMsg {
+    fn from(msg: MuxMsg) -> Self {
+        Self(msg)
+    }
+}
+
+#[cfg(feature = "pyo3")]
+impl From<PyMuxMsg> for MuxMsg {
+    fn from(msg: PyMuxMsg) -> Self {
+        msg.0
+    }
+}
+
+/// Python builds the messages a host sends with the static constructors and
+/// reads a message through `variant` and the field getters, which are `None`
+/// for variants without that field.
+#[cfg(feature = "pyo3")]
+#[pyo3::pymethods]
+impl PyMuxMsg {
+    #[staticmethod]
+    fn request_devices() -> Self {
+        Self(MuxMsg::RequestDevices)
+    }
+    #[staticmethod]
+    fn read_version() -> Self {
+        Self(MuxMsg::ReadVersion())
+    }
+    #[staticmethod]
+    fn subscribe_device_list() -> Self {
+        Self(MuxMsg::SubscribeDeviceList)
+    }
+    #[staticmethod]
+    fn unsubscribe_device_list() -> Self {
+        Self(MuxMsg::UnsubscribeDeviceList)
+    }
+    #[staticmethod]
+    fn send_to(dev: Uuid, pkt: Packet) -> Self {
+        Self(MuxMsg::SendTo(SendTo { dev, pkt }))
+    }
+    #[staticmethod]
+    fn send_to_many(devs: std::vec::Vec<Uuid>, pkt: Packet) -> pyo3::PyResult<Self> {
+        let devs = Vec::from_slice(&devs)
+            .map_err(|_| pyo3::exceptions::PyValueError::new_err("too many devices"))?;
+        Ok(Self(MuxMsg::SendToMany { devs, pkt }))
+    }
+    #[staticmethod]
+    fn broadcast(pkt: Packet) -> Self {
+        Self(MuxMsg::Broadcast { pkt })
+    }
+    #[staticmethod]
+    #[pyo3(signature = (dev, packet_types=None))]
+    fn set_forward_filter(dev: Uuid, packet_types: Option<PacketTypeSet>) -> Self {
+        Self(MuxMsg::SetForwardFilter { dev, packet_types })
+    }
+
+    /// The variant name, e.g. `"DevicePacket"`.
+    #[getter]
+    fn variant(&self) -> &'static str {
+        match &self.0 {
+            MuxMsg::DevicesSnapshot(_) => "DevicesSnapshot",
+            MuxMsg::DevicePacket(_) => "DevicePacket",
+            MuxMsg::RequestDevices => "RequestDevices",
+            MuxMsg::SendTo(_) => "SendTo",
+            MuxMsg::ReadVersion() => "ReadVersion",
+            MuxMsg::ReadVersionResponse(_) => "ReadVersionResponse",
+            MuxMsg::SubscribeDeviceList => "SubscribeDeviceList",
+            MuxMsg::UnsubscribeDeviceList => "UnsubscribeDeviceList",
+            MuxMsg::DeviceConnected { .. } => "DeviceConnected",
+            MuxMsg::DeviceDisconnected { .. } => "DeviceDisconnected",
+            MuxMsg::SendToMany { .. } => "SendToMany",
+            MuxMsg::Broadcast { .. } => "Broadcast",
+            MuxMsg::SendAcks(_) => "SendAcks",
+            MuxMsg::SetForwardFilter { .. } => "SetForwardFilter",
+        }
+    }
+    /// The device of a single-device message, see [`MuxMsgN::device_uuid`].
+    #[getter]
+    fn dev(&self) -> Option<Uuid> {
+        self.0.device_uuid()
+    }
+    /// The devices of a `DevicesSnapshot` or `SendToMany`.
+    #[getter]
+    fn devs(&self) -> Option<std::vec::Vec<Uuid>> {
+        match &self.0 {
+            MuxMsg::DevicesSnapshot(devs) | MuxMsg::SendToMany { devs, .. } => Some(devs.to_vec()),
+            _ => None,
+        }
+    }
+    #[getter]
+    fn pkt(&self) -> Option<Packet> {
+        match &self.0 {
+            MuxMsg::DevicePacket(DevicePacket { pkt, .. })
+            | MuxMsg::SendTo(SendTo { pkt, .. })
+            | MuxMsg::SendToMany { pkt, .. }
+            | MuxMsg::Broadcast { pkt } => Some(pkt.clone()),
+            _ => None,
+        }
+    }
+    #[getter]
+    fn version(&self) -> Option<Version> {
+        match &self.0 {
+            MuxMsg::ReadVersionResponse(v) => Some(*v),
+            _ => None,
+        }
+    }
+    #[getter]
+    fn product_id(&self) -> Option<u16> {
+        match &self.0 {
+            MuxMsg::DeviceConnected { product_id, .. } => Some(*product_id),
+            _ => None,
+        }
+    }
+    #[getter]
+    fn transport(&self) -> Option<TransportMode> {
+        match &self.0 {
+            MuxMsg::DeviceConnected { transport, .. } => Some(*transport),
+            _ => None,
+        }
+    }
+    #[getter]
+    fn rssi(&self) -> Option<i8> {
+        match &self.0 {
+            MuxMsg::DeviceConnected { rssi, .. } => *rssi,
+            _ => None,
+        }
+    }
+    #[getter]
+    fn reason(&self) -> Option<DisconnectReason> {
+        match &self.0 {
+            MuxMsg::DeviceDisconnected { reason, .. } => Some(*reason),
+            _ => None,
+        }
+    }
+    #[getter]
+    fn acks(&self) -> Option<std::vec::Vec<SendAck>> {
+        match &self.0 {
+            MuxMsg::SendAcks(acks) => Some(acks.to_vec()),
+            _ => None,
+        }
+    }
+    /// The filter of a `SetForwardFilter`, `None` if it lifts the filter.
+    #[getter]
+    fn packet_types(&self) -> Option<PacketTypeSet> {
+        match &self.0 {
+            MuxMsg::SetForwardFilter { packet_types, .. } => *packet_types,
+            _ => None,
+        }
+    }
+
+    fn __repr__(&self) -> std::string::String {
+        std::format!("{:?}", self.0)
+    }
+}
+
+/// A mux message for a dongle that tracks up to `N` devices.
+///
+/// Decoding a `DevicesSnapshot` with more than `N` devices fails rather than
+/// dropping the extra devices.
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
 #[derive(Clone, Debug)]
-pub enum MuxMsg {
-    DevicesSnapshot(Vec<Uuid, MAX_DEVICES>),
-    DevicePacket(DevicePacket),
+pub enum MuxMsgN<const N: usize> {
+    #[cfg_attr(feature = "minicbor", n(0))]
+    DevicesSnapshot(
+        #[cfg_attr(feature = "minicbor", n(0))]
+        #[cfg_attr(feature = "minicbor", cbor(with = "crate::heapless_vec_cbor"))]
+        Vec<Uuid, N>,
+    ),
+    #[cfg_attr(feature = "minicbor", n(1))]
+    DevicePacket(#[cfg_attr(feature = "minicbor", n(0))] DevicePacket),
+    #[cfg_attr(feature = "minicbor", n(2))]
     RequestDevices,
-    SendTo(SendTo),
+    #[cfg_attr(feature = "minicbor", n(3))]
+    SendTo(#[cfg_attr(feature = "minicbor", n(0))] SendTo),
+    #[cfg_attr(feature = "minicbor", n(4))]
     ReadVersion(),
-    ReadVersionResponse(Version),
+    #[cfg_attr(feature = "minicbor", n(5))]
+    ReadVersionResponse(#[cfg_attr(feature = "minicbor", n(0))] Version),
     /// Subscribe to device list changes. After subscribing, the dongle will send
-    /// DevicesSnapshot messages whenever devices connect or disconnect.
+    /// DevicesSnapshot messages, or DeviceConnected and DeviceDisconnected
+    /// events, whenever devices connect or disconnect.
+    #[cfg_attr(feature = "minicbor", n(6))]
     SubscribeDeviceList,
     /// Unsubscribe from device list changes.
+    #[cfg_attr(feature = "minicbor", n(7))]
     UnsubscribeDeviceList,
+    #[cfg_attr(feature = "minicbor", n(8))]
+    DeviceConnected {
+        #[cfg_attr(feature = "minicbor", n(0))]
+        uuid: Uuid,
+        /// A [`ProductId`](crate::ProductId) value.
+        #[cfg_attr(feature = "minicbor", n(1))]
+        product_id: u16,
+        #[cfg_attr(feature = "minicbor", n(2))]
+        transport: TransportMode,
+        /// Signal strength in dBm, for wireless links.
+        #[cfg_attr(feature = "minicbor", n(3))]
+        rssi: Option<i8>,
+    },
+    #[cfg_attr(feature = "minicbor", n(9))]
+    DeviceDisconnected {
+        #[cfg_attr(feature = "minicbor", n(0))]
+        uuid: Uuid,
+        #[cfg_attr(feature = "minicbor", n(1))]
+        reason: DisconnectReason,
+    },
+    /// Send `pkt` to each of `devs`. The dongle answers with `SendAcks`.
+    #[cfg_attr(feature = "minicbor", n(10))]
+    SendToMany {
+        #[cfg_attr(feature = "minicbor", n(0))]
+        #[cfg_attr(feature = "minicbor", cbor(with = "crate::heapless_vec_cbor"))]
+        devs: Vec<Uuid, N>,
+        #[cfg_attr(feature = "minicbor", n(1))]
+        pkt: Packet,
+    },
+    /// Send `pkt` to every connected device. The dongle answers with
+    /// `SendAcks`.
+    #[cfg_attr(feature = "minicbor", n(11))]
+    Broadcast {
+        #[cfg_attr(feature = "minicbor", n(0))]
+        pkt: Packet,
+    },
+    /// One ack per device targeted by a `SendToMany` or `Broadcast`.
+    #[cfg_attr(feature = "minicbor", n(12))]
+    SendAcks(
+        #[cfg_attr(feature = "minicbor", n(0))]
+        #[cfg_attr(feature = "minicbor", cbor(with = "crate::heapless_vec_cbor"))]
+        Vec<SendAck, N>,
+    ),
+    /// Only forward `DevicePacket`s from `dev` whose type is in
+    /// `packet_types`. `None` lifts the filter.
+    #[cfg_attr(feature = "minicbor", n(13))]
+    SetForwardFilter {
+        #[cfg_attr(feature = "minicbor", n(0))]
+        dev: Uuid,
+        #[cfg_attr(feature = "minicbor", n(1))]
+        packet_types: Option<PacketTypeSet>,
+    },
+}
+
+#[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisconnectReason {
+    #[cfg_attr(feature = "minicbor", n(0))]
+    Unknown,
+    /// The link timed out or the device went out of range.
+    #[cfg_attr(feature = "minicbor", n(1))]
+    LinkLost,
+    /// The device closed the connection.
+    #[cfg_attr(feature = "minicbor", n(2))]
+    Remote,
+    /// The dongle closed the connection, e.g. after its bond was cleared.
+    #[cfg_attr(feature = "minicbor", n(3))]
+    Local,
 }
 
 #[repr(C)]
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
 #[derive(Clone, Debug)]
 pub struct DevicePacket {
+    #[cfg_attr(feature = "minicbor", n(0))]
     pub dev: Uuid,
+    #[cfg_attr(feature = "minicbor", n(1))]
     pub pkt: Packet,
 }
 
 #[repr(C)]
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
 #[derive(Clone, Debug)]
 pub struct SendTo {
+    #[cfg_attr(feature = "minicbor", n(0))]
     pub dev: Uuid,
+    #[cfg_attr(feature = "minicbor", n(1))]
     pub pkt: Packet,
 }
 
+#[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendStatus {
+    #[cfg_attr(feature = "minicbor", n(0))]
+    Sent,
+    #[cfg_attr(feature = "minicbor", n(1))]
+    NotConnected,
+    /// The device's transmit queue was full.
+    #[cfg_attr(feature = "minicbor", n(2))]
+    QueueFull,
+    #[cfg_attr(feature = "minicbor", n(3))]
+    LinkError,
+    /// The dongle gave up waiting for the send to complete.
+    #[cfg_attr(feature = "minicbor", n(4))]
+    Timeout,
+}
+
+impl SendStatus {
+    pub fn is_sent(self) -> bool {
+        self == Self::Sent
+    }
+}
+
 #[repr(C)]
 #[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendAck {
+    #[cfg_attr(feature = "minicbor", n(0))]
+    pub dev: Uuid,
+    #[cfg_attr(feature = "minicbor", n(1))]
+    pub status: SendStatus,
+}
+
+#[repr(C)]
+#[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "minicbor",
+    derive(minicbor::Encode, minicbor::Decode, minicbor::CborLen)
+)]
 #[derive(Clone, Copy, Debug)]
 pub struct Version {
+    #[cfg_attr(feature = "minicbor", n(0))]
     pub protocol_semver: [u16; 3],
+    #[cfg_attr(feature = "minicbor", n(1))]
     pub firmware_semver: [u16; 3],
 }
 
@@ -58,30 +388,311 @@ impl Version {
             firmware_semver,
         }
     }
+
+    /// Checks a dongle's `ReadVersionResponse` against the mux protocol version
+    /// this crate speaks and pairs it with the dongle's
+    /// `ReadCapabilitiesResponse`.
+    pub fn negotiate(&self, features: Capabilities) -> Result<handshake::Negotiated, Error> {
+        handshake::negotiate_with(
+            SEMVER,
+            crate::Version {
+                protocol_semver: self.protocol_semver,
+                firmware_semver: self.firmware_semver,
+            },
+            features,
+        )
+    }
 }
 
 /// Convenience helpers for MuxMsg to access device UUIDs when the message
 /// references a single device. This returns the 6-byte device UUID where
 /// applicable (for `DevicePacket` and `SendTo`). Other variants return `None`.
-impl MuxMsg {
+impl<const N: usize> MuxMsgN<N> {
     /// If this message references a single device, return its 6-byte UUID.
     /// - `MuxMsg::DevicePacket` -> returns `Some(dev)`
-    /// - `MuxMsg::SendTo` -> returns `Some(dev)`
+    /// - `MuxMsg::SendTo` / `MuxMsg::SetForwardFilter` -> returns `Some(dev)`
+    /// - `MuxMsg::DeviceConnected` / `MuxMsg::DeviceDisconnected` -> returns `Some(uuid)`
     /// - otherwise -> `None`
     pub fn device_uuid(&self) -> Option<[u8; 6]> {
         match self {
-            MuxMsg::DevicePacket(dp) => Some(dp.dev),
-            MuxMsg::SendTo(s) => Some(s.dev),
+            Self::DevicePacket(dp) => Some(dp.dev),
+            Self::SendTo(s) => Some(s.dev),
+            Self::SetForwardFilter { dev, .. } => Some(*dev),
+            Self::DeviceConnected { uuid, .. } | Self::DeviceDisconnected { uuid, .. } => {
+                Some(*uuid)
+            }
+            _ => None,
+        }
+    }
+
+    /// If this message asks the dongle to send a packet, return the packet and
+    /// the devices it goes to. `connected` is the dongle's current device list,
+    /// which a `Broadcast` targets.
+    pub fn outgoing<'a>(&'a self, connected: &'a [Uuid]) -> Option<(&'a [Uuid], &'a Packet)> {
+        match self {
+            Self::SendTo(s) => Some((core::slice::from_ref(&s.dev), &s.pkt)),
+            Self::SendToMany { devs, pkt } => Some((devs, pkt)),
+            Self::Broadcast { pkt } => Some((connected, pkt)),
             _ => None,
         }
     }
 
     /// If this message includes a snapshot of devices, return a reference to the vector.
     /// Useful for callers that want to examine the snapshot without matching the enum.
-    pub fn devices_snapshot(&self) -> Option<&Vec<[u8; 6], MAX_DEVICES>> {
+    pub fn devices_snapshot(&self) -> Option<&Vec<[u8; 6], N>> {
         match self {
-            MuxMsg::DevicesSnapshot(devs) => Some(devs),
+            Self::DevicesSnapshot(devs) => Some(devs),
             _ => None,
         }
     }
 }
+
+/// A connected device as tracked by a [`DeviceTable`].
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceEntry {
+    pub uuid: Uuid,
+    /// `None` until a `DeviceConnected` event for the device is seen.
+    pub product_id: Option<u16>,
+    pub transport: Option<TransportMode>,
+    pub rssi: Option<i8>,
+}
+
+impl DeviceEntry {
+    const fn unknown(uuid: Uuid) -> Self {
+        Self {
+            uuid,
+            product_id: None,
+            transport: None,
+            rssi: None,
+        }
+    }
+}
+
+/// The host's view of the devices connected to a dongle, kept up to date from
+/// `DevicesSnapshot`, `DeviceConnected` and `DeviceDisconnected` messages.
+///
+/// Snapshots only carry UUIDs, so a device that first appears in a snapshot
+/// has no product, transport or RSSI until a `DeviceConnected` for it arrives.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceTable<const N: usize = MAX_DEVICES> {
+    devices: Vec<DeviceEntry, N>,
+}
+
+impl<const N: usize> DeviceTable<N> {
+    pub const fn new() -> Self {
+        Self {
+            devices: Vec::new(),
+        }
+    }
+
+    /// Updates the table from `msg`. Returns whether the set of connected
+    /// devices or any of their details changed.
+    ///
+    /// A snapshot replaces the device list, keeping the details of devices
+    /// that stay connected. A `DeviceConnected` for a new device is ignored if
+    /// the table is full. Other messages are ignored.
+    pub fn apply(&mut self, msg: &MuxMsgN<N>) -> bool {
+        match msg {
+            MuxMsgN::DevicesSnapshot(uuids) => self.apply_snapshot(uuids),
+            &MuxMsgN::DeviceConnected {
+                uuid,
+                product_id,
+                transport,
+                rssi,
+            } => {
+                let entry = DeviceEntry {
+                    uuid,
+                    product_id: Some(product_id),
+                    transport: Some(transport),
+                    rssi,
+                };
+                match self.devices.iter_mut().find(|d| d.uuid == uuid) {
+                    Some(d) => core::mem::replace(d, entry) != entry,
+                    None => self.devices.push(entry).is_ok(),
+                }
+            }
+            MuxMsgN::DeviceDisconnected { uuid, .. } => self.remove(uuid).is_some(),
+            _ => false,
+        }
+    }
+
+    fn apply_snapshot(&mut self, uuids: &[Uuid]) -> bool {
+        let before = self.devices.len();
+        self.devices.retain(|d| uuids.contains(&d.uuid));
+        let mut changed = self.devices.len() != before;
+        for &uuid in uuids {
+            if !self.contains(&uuid) {
+                // `uuids` holds at most `N` devices, so there is room.
+                let _ = self.devices.push(DeviceEntry::unknown(uuid));
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    pub fn get(&self, uuid: &Uuid) -> Option<&DeviceEntry> {
+        self.devices.iter().find(|d| d.uuid == *uuid)
+    }
+
+    pub fn contains(&self, uuid: &Uuid) -> bool {
+        self.get(uuid).is_some()
+    }
+
+    pub fn remove(&mut self, uuid: &Uuid) -> Option<DeviceEntry> {
+        let i = self.devices.iter().position(|d| d.uuid == *uuid)?;
+        Some(self.devices.remove(i))
+    }
+
+    pub fn clear(&mut self) {
+        self.devices.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Devices in the order they connected or were first seen.
+    pub fn iter(&self) -> impl Iterator<Item = &DeviceEntry> {
+        self.devices.iter()
+    }
+
+    pub fn uuids(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.devices.iter().map(|d| d.uuid)
+    }
+}
+
+/// Collects the per-device results of a `SendToMany` or `Broadcast` into one
+/// `SendAcks` reply.
+///
+/// The dongle creates an `AckSet` with the targeted devices, records each
+/// device's [`SendStatus`] as its send completes and replies once
+/// [`is_complete`](Self::is_complete) returns `true`, or on a timeout.
+#[derive(Clone, Debug)]
+pub struct AckSet<const N: usize = MAX_DEVICES> {
+    acks: Vec<(Uuid, Option<SendStatus>), N>,
+}
+
+impl<const N: usize> AckSet<N> {
+    /// Expects one ack from each of `devs`. Repeated devices are expected
+    /// once, and devices past the first `N` are ignored.
+    pub fn new(devs: &[Uuid]) -> Self {
+        let mut acks = Vec::new();
+        for &dev in devs {
+            if !acks.iter().any(|(d, _)| *d == dev) && acks.push((dev, None)).is_err() {
+                break;
+            }
+        }
+        Self { acks }
+    }
+
+    /// Records the result of the send to `dev`. Returns `false` if `dev` was
+    /// not targeted.
+    pub fn record(&mut self, dev: Uuid, status: SendStatus) -> bool {
+        match self.acks.iter_mut().find(|(d, _)| *d == dev) {
+            Some((_, s)) => {
+                *s = Some(status);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.acks.iter().all(|(_, s)| s.is_some())
+    }
+
+    /// Devices that have not acked yet.
+    pub fn pending(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.acks
+            .iter()
+            .filter(|(_, s)| s.is_none())
+            .map(|(d, _)| *d)
+    }
+
+    /// The `SendAcks` reply. Devices that have not acked are reported as
+    /// [`SendStatus::Timeout`].
+    pub fn finish(&self) -> MuxMsgN<N> {
+        MuxMsgN::SendAcks(
+            self.acks
+                .iter()
+                .map(|&(dev, s)| SendAck {
+                    dev,
+                    status: s.unwrap_or(SendStatus::Timeout),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// The dongle side of `SetForwardFilter`: decides which `DevicePacket`s are
+/// forwarded to the host.
+///
+/// Devices without a filter have all their packets forwarded. The dongle
+/// should [`remove`](Self::remove) a device's filter when it disconnects.
+#[derive(Clone, Debug, Default)]
+pub struct ForwardFilter<const N: usize = MAX_DEVICES> {
+    filters: Vec<(Uuid, PacketTypeSet), N>,
+}
+
+impl<const N: usize> ForwardFilter<N> {
+    pub const fn new() -> Self {
+        Self {
+            filters: Vec::new(),
+        }
+    }
+
+    /// Sets the filter for `dev`, or lifts it if `packet_types` is `None`.
+    /// Returns `false` if filters for `N` other devices are already set.
+    pub fn set(&mut self, dev: Uuid, packet_types: Option<PacketTypeSet>) -> bool {
+        let Some(packet_types) = packet_types else {
+            self.remove(&dev);
+            return true;
+        };
+        match self.filters.iter_mut().find(|(d, _)| *d == dev) {
+            Some((_, mask)) => {
+                *mask = packet_types;
+                true
+            }
+            None => self.filters.push((dev, packet_types)).is_ok(),
+        }
+    }
+
+    /// Applies a `SetForwardFilter`. Returns `false` for any other message or
+    /// if the filter could not be stored.
+    pub fn apply<const M: usize>(&mut self, msg: &MuxMsgN<M>) -> bool {
+        match *msg {
+            MuxMsgN::SetForwardFilter { dev, packet_types } => self.set(dev, packet_types),
+            _ => false,
+        }
+    }
+
+    pub fn remove(&mut self, dev: &Uuid) {
+        self.filters.retain(|(d, _)| d != dev);
+    }
+
+    pub fn clear(&mut self) {
+        self.filters.clear();
+    }
+
+    /// The packet types forwarded from `dev`, or `None` if it has no filter.
+    pub fn mask(&self, dev: &Uuid) -> Option<PacketTypeSet> {
+        self.filters
+            .iter()
+            .find(|(d, _)| d == dev)
+            .map(|&(_, mask)| mask)
+    }
+
+    pub fn allows(&self, dev: &Uuid, ty: PacketType) -> bool {
+        self.mask(dev).is_none_or(|mask| mask.contains(ty))
+    }
+
+    /// Whether `pkt` should be forwarded to the host.
+    pub fn should_forward(&self, pkt: &DevicePacket) -> bool {
+        self.allows(&pkt.dev, pkt.pkt.ty())
+    }
+}
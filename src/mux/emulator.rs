@@ -0,0 +1,527 @@
+//! A software stand-in for the mux dongle, for testing host code without one.
+//!
+//! [`Emulator`] plays the dongle side of [`MuxMsgN`] and [`UsbMuxCtrlMsgN`].
+//! Like [`Client`](crate::client::Client) it does no I/O: the host's messages
+//! are passed to [`Emulator::handle`] and [`Emulator::handle_ctrl`], and the
+//! dongle's replies are read back with [`Emulator::recv`] and
+//! [`Emulator::recv_ctrl`]. Virtual devices are connected and disconnected by
+//! the test, and their packets are injected through an [`Injector`], which
+//! can be moved to another thread.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::mpsc,
+    vec::Vec,
+};
+
+use super::{
+    AckSet, DevicePacket, DisconnectReason, ForwardFilter, MAX_DEVICES, MuxMsgN, SendStatus, Uuid,
+    Version,
+};
+use crate::{
+    Capabilities, Packet, ProductId,
+    control::{
+        AddBondError, BondedDevice,
+        device::TransportMode,
+        usb_mux::{BondStoreError, PairingError, UsbMuxCtrlMsgN},
+    },
+};
+
+/// A device connected to an [`Emulator`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VirtualDevice {
+    pub uuid: Uuid,
+    pub product_id: ProductId,
+    pub transport: TransportMode,
+    pub rssi: Option<i8>,
+}
+
+impl VirtualDevice {
+    /// A BLE ATS Lite.
+    pub fn new(uuid: Uuid) -> Self {
+        Self {
+            uuid,
+            product_id: ProductId::AtsLite,
+            transport: TransportMode::Ble,
+            rssi: Some(-50),
+        }
+    }
+}
+
+/// Injects packets into an [`Emulator`] as if a connected device sent them.
+#[derive(Clone, Debug)]
+pub struct Injector(mpsc::Sender<DevicePacket>);
+
+impl Injector {
+    /// Queues `pkt` from `dev`. Returns `false` if the emulator was dropped.
+    pub fn inject(&self, dev: Uuid, pkt: Packet) -> bool {
+        self.0.send(DevicePacket { dev, pkt }).is_ok()
+    }
+}
+
+/// An emulated mux dongle with room for `N` devices and `N` bonds.
+#[derive(Debug)]
+pub struct Emulator<const N: usize = MAX_DEVICES> {
+    firmware_semver: [u16; 3],
+    capabilities: Capabilities,
+    devices: Vec<VirtualDevice>,
+    bonds: Vec<BondedDevice>,
+    pairing: bool,
+    subscribed: bool,
+    filter: ForwardFilter<N>,
+    sent: HashMap<Uuid, Vec<Packet>>,
+    injected_tx: mpsc::Sender<DevicePacket>,
+    injected_rx: mpsc::Receiver<DevicePacket>,
+    outbox: VecDeque<MuxMsgN<N>>,
+    ctrl_outbox: VecDeque<UsbMuxCtrlMsgN<N>>,
+}
+
+impl<const N: usize> Emulator<N> {
+    pub fn new(firmware_semver: [u16; 3]) -> Self {
+        let (injected_tx, injected_rx) = mpsc::channel();
+        Self {
+            firmware_semver,
+            capabilities: Capabilities::default(),
+            devices: Vec::new(),
+            bonds: Vec::new(),
+            pairing: false,
+            subscribed: false,
+            filter: ForwardFilter::new(),
+            sent: HashMap::new(),
+            injected_tx,
+            injected_rx,
+            outbox: VecDeque::new(),
+            ctrl_outbox: VecDeque::new(),
+        }
+    }
+
+    /// Sets what the dongle reports in answer to `ReadCapabilities`.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    pub fn injector(&self) -> Injector {
+        Injector(self.injected_tx.clone())
+    }
+
+    pub fn devices(&self) -> &[VirtualDevice] {
+        &self.devices
+    }
+
+    pub fn bonds(&self) -> &[BondedDevice] {
+        &self.bonds
+    }
+
+    /// Whether a pairing started by `StartPairing` is in progress.
+    pub fn is_pairing(&self) -> bool {
+        self.pairing
+    }
+
+    /// Connects `dev`, replacing any device with the same UUID. Returns
+    /// `false` if `N` other devices are connected.
+    ///
+    /// A subscribed host gets a `DeviceConnected` followed by a
+    /// `DevicesSnapshot`.
+    pub fn connect(&mut self, dev: VirtualDevice) -> bool {
+        match self.devices.iter().position(|d| d.uuid == dev.uuid) {
+            Some(i) => self.devices[i] = dev,
+            None if self.devices.len() < N => self.devices.push(dev),
+            None => return false,
+        }
+        if self.subscribed {
+            self.outbox.push_back(MuxMsgN::DeviceConnected {
+                uuid: dev.uuid,
+                product_id: dev.product_id as u16,
+                transport: dev.transport,
+                rssi: dev.rssi,
+            });
+            self.outbox.push_back(self.snapshot());
+        }
+        true
+    }
+
+    /// Disconnects `uuid`. Returns `false` if it was not connected.
+    ///
+    /// A subscribed host gets a `DeviceDisconnected` followed by a
+    /// `DevicesSnapshot`.
+    pub fn disconnect(&mut self, uuid: Uuid, reason: DisconnectReason) -> bool {
+        let Some(i) = self.devices.iter().position(|d| d.uuid == uuid) else {
+            return false;
+        };
+        self.devices.remove(i);
+        self.filter.remove(&uuid);
+        if self.subscribed {
+            self.outbox
+                .push_back(MuxMsgN::DeviceDisconnected { uuid, reason });
+            self.outbox.push_back(self.snapshot());
+        }
+        true
+    }
+
+    /// Finishes the pairing in progress by bonding with `uuid`. Returns `false`
+    /// if no pairing is in progress.
+    ///
+    /// If the bond store is full the host gets a `BondStoreError` instead of a
+    /// `PairingResult`.
+    pub fn complete_pairing(&mut self, uuid: Uuid, name: &str) -> bool {
+        if !core::mem::take(&mut self.pairing) {
+            return false;
+        }
+        let mut bond = BondedDevice {
+            uuid,
+            name: heapless::String::new(),
+        };
+        for c in name.chars() {
+            if bond.name.push(c).is_err() {
+                break;
+            }
+        }
+        let reply = match self.insert_bond(bond.clone()) {
+            Ok(()) => UsbMuxCtrlMsgN::PairingResult(Ok(bond)),
+            Err(_) => UsbMuxCtrlMsgN::BondStoreError(BondStoreError::Full),
+        };
+        self.ctrl_outbox.push_back(reply);
+        true
+    }
+
+    /// Fails the pairing in progress with `err`. Returns `false` if no pairing
+    /// is in progress.
+    pub fn fail_pairing(&mut self, err: PairingError) -> bool {
+        if !core::mem::take(&mut self.pairing) {
+            return false;
+        }
+        self.ctrl_outbox
+            .push_back(UsbMuxCtrlMsgN::PairingResult(Err(err)));
+        true
+    }
+
+    /// Takes the packets the host has sent to `uuid` so far.
+    pub fn take_sent(&mut self, uuid: &Uuid) -> Vec<Packet> {
+        self.sent.remove(uuid).unwrap_or_default()
+    }
+
+    /// Handles a mux message from the host.
+    pub fn handle(&mut self, msg: MuxMsgN<N>) {
+        match msg {
+            MuxMsgN::RequestDevices => self.outbox.push_back(self.snapshot()),
+            MuxMsgN::ReadVersion() => {
+                self.outbox
+                    .push_back(MuxMsgN::ReadVersionResponse(Version::new(
+                        self.firmware_semver,
+                    )))
+            }
+            MuxMsgN::SubscribeDeviceList => self.subscribed = true,
+            MuxMsgN::UnsubscribeDeviceList => self.subscribed = false,
+            MuxMsgN::SendTo(s) => {
+                self.send(s.dev, &s.pkt);
+            }
+            MuxMsgN::SendToMany { .. } | MuxMsgN::Broadcast { .. } => {
+                let connected: Vec<Uuid> = self.devices.iter().map(|d| d.uuid).collect();
+                let Some((devs, pkt)) = msg.outgoing(&connected) else {
+                    return;
+                };
+                let mut acks = AckSet::<N>::new(devs);
+                for &dev in devs {
+                    let status = self.send(dev, pkt);
+                    acks.record(dev, status);
+                }
+                self.outbox.push_back(acks.finish());
+            }
+            MuxMsgN::SetForwardFilter { .. } => {
+                self.filter.apply(&msg);
+            }
+            // Messages only a dongle sends.
+            MuxMsgN::DevicesSnapshot(_)
+            | MuxMsgN::DevicePacket(_)
+            | MuxMsgN::ReadVersionResponse(_)
+            | MuxMsgN::DeviceConnected { .. }
+            | MuxMsgN::DeviceDisconnected { .. }
+            | MuxMsgN::SendAcks(_) => {}
+        }
+    }
+
+    /// Handles a control message from the host.
+    pub fn handle_ctrl(&mut self, msg: UsbMuxCtrlMsgN<N>) {
+        use UsbMuxCtrlMsgN as M;
+        let reply = match msg {
+            M::ReadVersion() => M::ReadVersionResponse(crate::Version::new(self.firmware_semver)),
+            M::ListBonds => M::ListBondsResponse(self.bonds.iter().cloned().collect()),
+            M::ClearBonds => {
+                self.bonds.clear();
+                M::ClearBondsResponse(Ok(()))
+            }
+            M::StartPairing(_) => {
+                self.pairing = true;
+                M::StartPairingResponse
+            }
+            M::CancelPairing => {
+                self.fail_pairing(PairingError::Cancelled);
+                return;
+            }
+            M::AddBond(entry) => M::AddBondResponse(self.insert_bond(BondedDevice {
+                uuid: entry.bd_addr,
+                name: heapless::String::new(),
+            })),
+            M::UpdateBondName { uuid, name } => {
+                if let Some(b) = self.bonds.iter_mut().find(|b| b.uuid == uuid) {
+                    b.name = name;
+                }
+                return;
+            }
+            M::ReadCapabilities => M::ReadCapabilitiesResponse(self.capabilities),
+            // Messages only a dongle sends.
+            M::ReadVersionResponse(_)
+            | M::ListBondsResponse(_)
+            | M::ClearBondsResponse(_)
+            | M::BondStoreError(_)
+            | M::StartPairingResponse
+            | M::PairingResult(_)
+            | M::AddBondResponse(_)
+            | M::ReadCapabilitiesResponse(_) => return,
+        };
+        self.ctrl_outbox.push_back(reply);
+    }
+
+    /// The next mux message for the host. Injected device packets are
+    /// forwarded here, unless their device is not connected or the host's
+    /// forward filter drops them.
+    pub fn recv(&mut self) -> Option<MuxMsgN<N>> {
+        while let Ok(dp) = self.injected_rx.try_recv() {
+            if self.devices.iter().any(|d| d.uuid == dp.dev) && self.filter.should_forward(&dp) {
+                self.outbox.push_back(MuxMsgN::DevicePacket(dp));
+            }
+        }
+        self.outbox.pop_front()
+    }
+
+    /// The next control message for the host.
+    pub fn recv_ctrl(&mut self) -> Option<UsbMuxCtrlMsgN<N>> {
+        self.ctrl_outbox.pop_front()
+    }
+
+    fn snapshot(&self) -> MuxMsgN<N> {
+        MuxMsgN::DevicesSnapshot(self.devices.iter().map(|d| d.uuid).collect())
+    }
+
+    fn send(&mut self, dev: Uuid, pkt: &Packet) -> SendStatus {
+        if !self.devices.iter().any(|d| d.uuid == dev) {
+            return SendStatus::NotConnected;
+        }
+        self.sent.entry(dev).or_default().push(pkt.clone());
+        SendStatus::Sent
+    }
+
+    fn insert_bond(&mut self, bond: BondedDevice) -> Result<(), AddBondError> {
+        match self.bonds.iter().position(|b| b.uuid == bond.uuid) {
+            Some(i) => self.bonds[i] = bond,
+            None if self.bonds.len() < N => self.bonds.push(bond),
+            None => return Err(AddBondError::Full),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ImpactReport, PacketData, PacketType, PacketTypeSet,
+        control::{BondEntry, usb_mux::StartPairing},
+    };
+
+    const A: Uuid = [1; 6];
+    const B: Uuid = [2; 6];
+
+    fn impact(id: u8) -> Packet {
+        Packet {
+            data: PacketData::ImpactReport(ImpactReport { timestamp: 1 }),
+            id,
+        }
+    }
+
+    #[test]
+    fn answers_requests_for_devices_and_version() {
+        let mut emu: Emulator = Emulator::new([1, 2, 3]);
+        emu.connect(VirtualDevice::new(A));
+        emu.connect(VirtualDevice::new(B));
+
+        emu.handle(MuxMsgN::RequestDevices);
+        let Some(MuxMsgN::DevicesSnapshot(devs)) = emu.recv() else {
+            panic!("expected a snapshot");
+        };
+        assert_eq!(devs, [A, B]);
+
+        emu.handle(MuxMsgN::ReadVersion());
+        let Some(MuxMsgN::ReadVersionResponse(v)) = emu.recv() else {
+            panic!("expected a version");
+        };
+        assert_eq!(v.firmware_semver, [1, 2, 3]);
+        assert_eq!(v.protocol_semver, Version::new([0; 3]).protocol_semver);
+
+        emu.handle_ctrl(UsbMuxCtrlMsgN::ReadVersion());
+        let Some(UsbMuxCtrlMsgN::ReadVersionResponse(v)) = emu.recv_ctrl() else {
+            panic!("expected a version");
+        };
+        assert_eq!(v.firmware_semver, [1, 2, 3]);
+        assert!(emu.recv().is_none());
+    }
+
+    #[test]
+    fn pairing_bonds_the_device() {
+        let mut emu: Emulator = Emulator::new([0; 3]);
+        assert!(!emu.complete_pairing(A, "a"));
+
+        emu.handle_ctrl(UsbMuxCtrlMsgN::StartPairing(StartPairing {
+            timeout_ms: 1000,
+        }));
+        assert!(matches!(
+            emu.recv_ctrl(),
+            Some(UsbMuxCtrlMsgN::StartPairingResponse)
+        ));
+        assert!(emu.is_pairing());
+
+        assert!(emu.complete_pairing(A, "a"));
+        assert!(!emu.is_pairing());
+        let Some(UsbMuxCtrlMsgN::PairingResult(Ok(bond))) = emu.recv_ctrl() else {
+            panic!("expected a pairing result");
+        };
+        assert_eq!(bond.uuid, A);
+        assert_eq!(bond.name, "a");
+
+        emu.handle_ctrl(UsbMuxCtrlMsgN::ListBonds);
+        let Some(UsbMuxCtrlMsgN::ListBondsResponse(bonds)) = emu.recv_ctrl() else {
+            panic!("expected the bond list");
+        };
+        assert_eq!(bonds.len(), 1);
+        assert_eq!(bonds[0].uuid, A);
+    }
+
+    #[test]
+    fn cancelling_fails_the_pairing() {
+        let mut emu: Emulator = Emulator::new([0; 3]);
+        emu.handle_ctrl(UsbMuxCtrlMsgN::StartPairing(StartPairing {
+            timeout_ms: 1000,
+        }));
+        emu.recv_ctrl();
+
+        emu.handle_ctrl(UsbMuxCtrlMsgN::CancelPairing);
+        assert!(matches!(
+            emu.recv_ctrl(),
+            Some(UsbMuxCtrlMsgN::PairingResult(Err(PairingError::Cancelled)))
+        ));
+        assert!(!emu.is_pairing());
+        assert!(!emu.complete_pairing(A, "a"));
+        assert!(emu.bonds().is_empty());
+
+        // With no pairing in progress there is nothing to cancel.
+        emu.handle_ctrl(UsbMuxCtrlMsgN::CancelPairing);
+        assert!(emu.recv_ctrl().is_none());
+    }
+
+    #[test]
+    fn acks_report_disconnected_devices() {
+        let mut emu: Emulator = Emulator::new([0; 3]);
+        emu.connect(VirtualDevice::new(A));
+
+        let devs = [A, B].into_iter().collect();
+        emu.handle(MuxMsgN::SendToMany {
+            devs,
+            pkt: impact(1),
+        });
+        let Some(MuxMsgN::SendAcks(acks)) = emu.recv() else {
+            panic!("expected acks");
+        };
+        assert_eq!(acks.len(), 2);
+        assert_eq!(acks[0].dev, A);
+        assert_eq!(acks[0].status, SendStatus::Sent);
+        assert_eq!(acks[1].dev, B);
+        assert_eq!(acks[1].status, SendStatus::NotConnected);
+        assert_eq!(emu.take_sent(&A).len(), 1);
+        assert!(emu.take_sent(&B).is_empty());
+
+        emu.disconnect(A, DisconnectReason::LinkLost);
+        emu.handle(MuxMsgN::Broadcast { pkt: impact(2) });
+        let Some(MuxMsgN::SendAcks(acks)) = emu.recv() else {
+            panic!("expected acks");
+        };
+        assert!(acks.is_empty());
+        assert!(emu.take_sent(&A).is_empty());
+    }
+
+    #[test]
+    fn forward_filter_drops_injected_packets() {
+        let mut emu: Emulator = Emulator::new([0; 3]);
+        emu.connect(VirtualDevice::new(A));
+        emu.connect(VirtualDevice::new(B));
+        let injector = emu.injector();
+
+        let packet_types: PacketTypeSet = [PacketType::Ack()].into_iter().collect();
+        emu.handle(MuxMsgN::SetForwardFilter {
+            dev: A,
+            packet_types: Some(packet_types),
+        });
+        injector.inject(A, impact(1));
+        injector.inject(B, impact(2));
+        let Some(MuxMsgN::DevicePacket(dp)) = emu.recv() else {
+            panic!("expected a device packet");
+        };
+        assert_eq!((dp.dev, dp.pkt.id), (B, 2));
+        assert!(emu.recv().is_none());
+
+        emu.handle(MuxMsgN::SetForwardFilter {
+            dev: A,
+            packet_types: None,
+        });
+        injector.inject(A, impact(3));
+        let Some(MuxMsgN::DevicePacket(dp)) = emu.recv() else {
+            panic!("expected a device packet");
+        };
+        assert_eq!((dp.dev, dp.pkt.id), (A, 3));
+    }
+
+    #[test]
+    fn a_full_bond_store_refuses_new_bonds() {
+        let mut emu: Emulator<2> = Emulator::new([0; 3]);
+        for uuid in [A, B] {
+            let entry = BondEntry {
+                bd_addr: uuid,
+                ..Default::default()
+            };
+            emu.handle_ctrl(UsbMuxCtrlMsgN::AddBond(entry));
+            assert!(matches!(
+                emu.recv_ctrl(),
+                Some(UsbMuxCtrlMsgN::AddBondResponse(Ok(())))
+            ));
+        }
+        let entry = BondEntry {
+            bd_addr: [3; 6],
+            ..Default::default()
+        };
+        emu.handle_ctrl(UsbMuxCtrlMsgN::AddBond(entry));
+        assert!(matches!(
+            emu.recv_ctrl(),
+            Some(UsbMuxCtrlMsgN::AddBondResponse(Err(AddBondError::Full)))
+        ));
+
+        emu.handle_ctrl(UsbMuxCtrlMsgN::StartPairing(StartPairing {
+            timeout_ms: 1000,
+        }));
+        emu.recv_ctrl();
+        assert!(emu.complete_pairing([3; 6], "c"));
+        assert!(matches!(
+            emu.recv_ctrl(),
+            Some(UsbMuxCtrlMsgN::BondStoreError(BondStoreError::Full))
+        ));
+        assert_eq!(emu.bonds().len(), 2);
+
+        // Re-bonding a known device replaces its bond.
+        emu.handle_ctrl(UsbMuxCtrlMsgN::AddBond(BondEntry {
+            bd_addr: A,
+            ..Default::default()
+        }));
+        assert!(matches!(
+            emu.recv_ctrl(),
+            Some(UsbMuxCtrlMsgN::AddBondResponse(Ok(())))
+        ));
+    }
+}
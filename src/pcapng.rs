@@ -0,0 +1,657 @@
+//! pcapng export and import, for sharing captures with tools like Wireshark.
+//!
+//! Each kind of message gets its own interface and link type from the
+//! `LINKTYPE_USER` range: [`Packet`]s are stored in their binary encoding,
+//! mux and control messages in their minicbor encoding. The direction of a
+//! frame goes in the standard `epb_flags` option, and the device and transport
+//! go in the packet comment as `dev=01:02:03:04:05:06 transport=ble`.
+//!
+//! The reader accepts files of either byte order and skips blocks and
+//! interfaces it does not know.
+
+use std::{
+    fmt::{Display, Write as _},
+    io::{self, Read, Write},
+    string::String,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+    vec::Vec,
+};
+
+use crate::{
+    Packet,
+    capture::{CaptureError, Header, Record, RecordBody},
+    control::{
+        device::{DeviceMsg, TransportMode},
+        usb_mux::UsbMuxCtrlMsg,
+    },
+    mux::{MuxMsg, Uuid},
+};
+
+pub const LINKTYPE_PACKET: u16 = 147;
+pub const LINKTYPE_MUX: u16 = 148;
+pub const LINKTYPE_USB_MUX_CTRL: u16 = 149;
+pub const LINKTYPE_DEVICE_CTRL: u16 = 150;
+
+/// Interfaces in the order the writer describes them.
+const INTERFACES: [(u16, &str); 4] = [
+    (LINKTYPE_PACKET, "packets"),
+    (LINKTYPE_MUX, "mux"),
+    (LINKTYPE_USB_MUX_CTRL, "usb mux control"),
+    (LINKTYPE_DEVICE_CTRL, "device control"),
+];
+
+const SHB: u32 = 0x0a0d_0d0a;
+const IDB: u32 = 0x0000_0001;
+const EPB: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+const OPT_END: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const SHB_USERAPPL: u16 = 4;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+const EPB_FLAGS: u16 = 2;
+
+/// Largest block the reader accepts.
+const MAX_BLOCK_LEN: u32 = 1 << 20;
+
+#[derive(Clone, Debug)]
+pub enum Message {
+    Packet(Packet),
+    Mux(MuxMsg),
+    UsbMuxCtrl(UsbMuxCtrlMsg),
+    Device(DeviceMsg),
+}
+
+impl Message {
+    fn interface(&self) -> u32 {
+        match self {
+            Self::Packet(_) => 0,
+            Self::Mux(_) => 1,
+            Self::UsbMuxCtrl(_) => 2,
+            Self::Device(_) => 3,
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<(), PcapngError> {
+        let encoded = match self {
+            Self::Packet(pkt) => {
+                pkt.serialize_to_vec(buf);
+                Ok(())
+            }
+            Self::Mux(msg) => crate::vec_cbor::encode(msg, buf),
+            Self::UsbMuxCtrl(msg) => crate::vec_cbor::encode(msg, buf),
+            Self::Device(msg) => crate::vec_cbor::encode(msg, buf),
+        };
+        encoded.map_err(|_| PcapngError::Encode)
+    }
+
+    fn decode(linktype: u16, mut bytes: &[u8]) -> Option<Result<Self, PcapngError>> {
+        let msg = match linktype {
+            LINKTYPE_PACKET => Packet::parse(&mut bytes).ok().map(Self::Packet),
+            LINKTYPE_MUX => minicbor::decode(bytes).ok().map(Self::Mux),
+            LINKTYPE_USB_MUX_CTRL => minicbor::decode(bytes).ok().map(Self::UsbMuxCtrl),
+            LINKTYPE_DEVICE_CTRL => minicbor::decode(bytes).ok().map(Self::Device),
+            _ => return None,
+        };
+        Some(msg.ok_or(PcapngError::Decode))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// From a device or dongle to the host.
+    Inbound,
+    /// From the host to a device or dongle.
+    Outbound,
+}
+
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// Time since the Unix epoch.
+    pub timestamp: Duration,
+    pub direction: Option<Direction>,
+    pub dev: Option<Uuid>,
+    pub transport: Option<TransportMode>,
+    pub message: Message,
+}
+
+impl Frame {
+    /// A frame timestamped now, with no direction, device or transport.
+    pub fn new(message: Message) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            direction: None,
+            dev: None,
+            transport: None,
+            message,
+        }
+    }
+
+    /// A record from a [`capture`](crate::capture) file, as received by the
+    /// host. A mux `DevicePacket` keeps its device. Fails with
+    /// [`CaptureError::Decode`] if the record's time is past what a `u64` of
+    /// microseconds since the epoch holds.
+    pub fn from_capture(header: &Header, record: Record) -> Result<Self, CaptureError> {
+        let unix_us = header
+            .started_unix_us
+            .checked_add(record.time_us)
+            .ok_or(CaptureError::Decode)?;
+        let (dev, message) = match record.body {
+            RecordBody::Packet(pkt) => (None, Message::Packet(pkt)),
+            RecordBody::Mux(msg) => (msg.device_uuid(), Message::Mux(msg)),
+        };
+        Ok(Self {
+            timestamp: Duration::from_micros(unix_us),
+            direction: Some(Direction::Inbound),
+            dev,
+            transport: None,
+            message,
+        })
+    }
+
+    fn comment(&self) -> Option<String> {
+        let mut comment = String::new();
+        if let Some(dev) = self.dev {
+            let [a, b, c, d, e, f] = dev;
+            let _ = write!(
+                comment,
+                "dev={a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{f:02x}"
+            );
+        }
+        if let Some(transport) = self.transport {
+            let sep = if comment.is_empty() { "" } else { " " };
+            let name = match transport {
+                TransportMode::Usb => "usb",
+                TransportMode::Ble => "ble",
+            };
+            let _ = write!(comment, "{sep}transport={name}");
+        }
+        (!comment.is_empty()).then_some(comment)
+    }
+
+    /// Picks the device and transport out of a comment written by
+    /// [`Frame::comment`], ignoring anything else in it.
+    fn parse_comment(&mut self, comment: &str) {
+        for field in comment.split_whitespace() {
+            match field.split_once('=') {
+                Some(("dev", dev)) => self.dev = parse_uuid(dev).or(self.dev),
+                Some(("transport", "usb")) => self.transport = Some(TransportMode::Usb),
+                Some(("transport", "ble")) => self.transport = Some(TransportMode::Ble),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn parse_uuid(s: &str) -> Option<Uuid> {
+    let mut uuid = [0; 6];
+    let mut parts = s.split(':');
+    for b in &mut uuid {
+        *b = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    parts.next().is_none().then_some(uuid)
+}
+
+#[derive(Debug)]
+pub enum PcapngError {
+    Io(io::Error),
+    /// The file is not valid pcapng.
+    Format(&'static str),
+    Encode,
+    /// A frame on one of our interfaces does not hold a valid message.
+    Decode,
+}
+
+impl Display for PcapngError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use PcapngError as S;
+        match self {
+            S::Io(e) => write!(f, "io error: {e}"),
+            S::Format(what) => write!(f, "invalid pcapng: {what}"),
+            S::Encode => write!(f, "failed to encode message"),
+            S::Decode => write!(f, "failed to decode message"),
+        }
+    }
+}
+
+impl std::error::Error for PcapngError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PcapngError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+/// Writes a little-endian pcapng file with one section.
+#[derive(Debug)]
+pub struct PcapngWriter<W: Write> {
+    inner: W,
+    body: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Writes the section header and describes the interfaces.
+    pub fn new(inner: W) -> Result<Self, PcapngError> {
+        let mut w = Self {
+            inner,
+            body: Vec::new(),
+            data: Vec::new(),
+        };
+        w.body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        w.body.extend_from_slice(&1u16.to_le_bytes());
+        w.body.extend_from_slice(&0u16.to_le_bytes());
+        // Section length not specified.
+        w.body.extend_from_slice(&(-1i64).to_le_bytes());
+        push_option(&mut w.body, SHB_USERAPPL, b"protodongers");
+        push_option(&mut w.body, OPT_END, &[]);
+        w.write_block(SHB)?;
+        for (linktype, name) in INTERFACES {
+            w.body.extend_from_slice(&linktype.to_le_bytes());
+            w.body.extend_from_slice(&0u16.to_le_bytes());
+            // No snap length limit.
+            w.body.extend_from_slice(&0u32.to_le_bytes());
+            push_option(&mut w.body, IF_NAME, name.as_bytes());
+            // Microsecond timestamps.
+            push_option(&mut w.body, IF_TSRESOL, &[6]);
+            push_option(&mut w.body, OPT_END, &[]);
+            w.write_block(IDB)?;
+        }
+        Ok(w)
+    }
+
+    pub fn write(&mut self, frame: &Frame) -> Result<(), PcapngError> {
+        self.data.clear();
+        frame.message.encode(&mut self.data)?;
+        let ts = frame.timestamp.as_micros() as u64;
+        self.body
+            .extend_from_slice(&frame.message.interface().to_le_bytes());
+        self.body
+            .extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        self.body.extend_from_slice(&(ts as u32).to_le_bytes());
+        let len = self.data.len() as u32;
+        self.body.extend_from_slice(&len.to_le_bytes());
+        self.body.extend_from_slice(&len.to_le_bytes());
+        self.body.extend_from_slice(&self.data);
+        pad(&mut self.body);
+        if let Some(direction) = frame.direction {
+            let flags: u32 = match direction {
+                Direction::Inbound => 0b01,
+                Direction::Outbound => 0b10,
+            };
+            push_option(&mut self.body, EPB_FLAGS, &flags.to_le_bytes());
+        }
+        if let Some(comment) = frame.comment() {
+            push_option(&mut self.body, OPT_COMMENT, comment.as_bytes());
+        }
+        push_option(&mut self.body, OPT_END, &[]);
+        self.write_block(EPB)
+    }
+
+    fn write_block(&mut self, ty: u32) -> Result<(), PcapngError> {
+        let total = (self.body.len() + 12) as u32;
+        self.inner.write_all(&ty.to_le_bytes())?;
+        self.inner.write_all(&total.to_le_bytes())?;
+        self.inner.write_all(&self.body)?;
+        self.inner.write_all(&total.to_le_bytes())?;
+        self.body.clear();
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), PcapngError> {
+        Ok(self.inner.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Interface {
+    linktype: u16,
+    tsresol: u8,
+}
+
+/// Reads the frames on our interfaces from a pcapng file.
+#[derive(Debug)]
+pub struct PcapngReader<R: Read> {
+    inner: R,
+    big_endian: bool,
+    interfaces: Vec<Interface>,
+    block: Vec<u8>,
+}
+
+impl<R: Read> PcapngReader<R> {
+    /// Checks that the file starts with a section header.
+    pub fn new(inner: R) -> Result<Self, PcapngError> {
+        let mut r = Self {
+            inner,
+            big_endian: false,
+            interfaces: Vec::new(),
+            block: Vec::new(),
+        };
+        match r.read_block()? {
+            Some(SHB) => Ok(r),
+            _ => Err(PcapngError::Format("missing section header")),
+        }
+    }
+
+    /// The next frame on one of our interfaces, or `None` at the end of the
+    /// file.
+    pub fn read_frame(&mut self) -> Result<Option<Frame>, PcapngError> {
+        loop {
+            let Some(ty) = self.read_block()? else {
+                return Ok(None);
+            };
+            match ty {
+                IDB => self.read_interface()?,
+                EPB => {
+                    if let Some(frame) = self.read_packet().transpose() {
+                        return frame.map(Some);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn u16(&self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        }
+    }
+
+    fn u32(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+
+    /// Reads the next block body into `self.block` and returns its type, or
+    /// `None` at the end of the file. A section header resets the byte order
+    /// and interfaces.
+    fn read_block(&mut self) -> Result<Option<u32>, PcapngError> {
+        let mut head = [0; 8];
+        match self.inner.read_exact(&mut head[..4]) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        self.inner.read_exact(&mut head[4..])?;
+        let ty = self.u32(&head);
+        if ty == SHB {
+            let mut magic = [0; 4];
+            self.inner.read_exact(&mut magic)?;
+            self.big_endian = match magic {
+                [0x1a, 0x2b, 0x3c, 0x4d] => true,
+                [0x4d, 0x3c, 0x2b, 0x1a] => false,
+                _ => return Err(PcapngError::Format("bad byte-order magic")),
+            };
+            self.interfaces.clear();
+        }
+        let total = self.u32(&head[4..]);
+        if total > MAX_BLOCK_LEN
+            || !total.is_multiple_of(4)
+            || total < 12 + 4 * u32::from(ty == SHB)
+        {
+            return Err(PcapngError::Format("bad block length"));
+        }
+        let read = 8 + 4 * usize::from(ty == SHB);
+        self.block.resize(total as usize - read, 0);
+        self.inner.read_exact(&mut self.block)?;
+        let trailer = self.block.len() - 4;
+        if self.u32(&self.block[trailer..]) != total {
+            return Err(PcapngError::Format("block lengths differ"));
+        }
+        self.block.truncate(trailer);
+        Ok(Some(ty))
+    }
+
+    /// The options at `offset` in the current block as `(code, value)` pairs.
+    fn options(&self, mut offset: usize) -> impl Iterator<Item = (u16, &[u8])> {
+        core::iter::from_fn(move || {
+            let head = self.block.get(offset..offset + 4)?;
+            let (code, len) = (self.u16(head), usize::from(self.u16(&head[2..])));
+            let value = self.block.get(offset + 4..offset + 4 + len)?;
+            offset += 4 + len.next_multiple_of(4);
+            (code != OPT_END).then_some((code, value))
+        })
+    }
+
+    fn read_interface(&mut self) -> Result<(), PcapngError> {
+        if self.block.len() < 8 {
+            return Err(PcapngError::Format("short interface description"));
+        }
+        let linktype = self.u16(&self.block);
+        let tsresol = self
+            .options(8)
+            .find(|&(code, _)| code == IF_TSRESOL)
+            .and_then(|(_, v)| v.first().copied())
+            .unwrap_or(6);
+        self.interfaces.push(Interface { linktype, tsresol });
+        Ok(())
+    }
+
+    fn read_packet(&mut self) -> Result<Option<Frame>, PcapngError> {
+        let b = &self.block;
+        if b.len() < 20 {
+            return Err(PcapngError::Format("short packet block"));
+        }
+        let interface = self
+            .interfaces
+            .get(self.u32(b) as usize)
+            .copied()
+            .ok_or(PcapngError::Format("unknown interface"))?;
+        let ts = u64::from(self.u32(&b[4..])) << 32 | u64::from(self.u32(&b[8..]));
+        let len = self.u32(&b[12..]) as usize;
+        let data = b
+            .get(20..20 + len)
+            .ok_or(PcapngError::Format("packet data overruns block"))?;
+        let Some(message) = Message::decode(interface.linktype, data) else {
+            return Ok(None);
+        };
+        let mut frame = Frame {
+            timestamp: timestamp(ts, interface.tsresol)?,
+            direction: None,
+            dev: None,
+            transport: None,
+            message: message?,
+        };
+        for (code, value) in self.options(20 + len.next_multiple_of(4)) {
+            match code {
+                EPB_FLAGS if value.len() == 4 => {
+                    frame.direction = match self.u32(value) & 0b11 {
+                        0b01 => Some(Direction::Inbound),
+                        0b10 => Some(Direction::Outbound),
+                        _ => None,
+                    }
+                }
+                OPT_COMMENT => {
+                    if let Ok(comment) = core::str::from_utf8(value) {
+                        frame.parse_comment(comment);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(Some(frame))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Iterator for PcapngReader<R> {
+    type Item = Result<Frame, PcapngError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+/// Converts a timestamp in units of `tsresol` to a duration. A resolution
+/// with the top bit clear is a negative power of 10, otherwise of 2.
+fn timestamp(ts: u64, tsresol: u8) -> Result<Duration, PcapngError> {
+    let secs = match tsresol {
+        0..=9 => {
+            let per_sec = 10u64.pow(tsresol.into());
+            let nanos = (ts % per_sec) * 10u64.pow(9 - u32::from(tsresol));
+            return Ok(Duration::new(ts / per_sec, nanos as u32));
+        }
+        0x80.. => ts as f64 / 2f64.powi(i32::from(tsresol & 0x7f)),
+        _ => ts as f64 / 10f64.powi(i32::from(tsresol)),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|_| PcapngError::Format("timestamp out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ImpactReport, PacketData, PropKind, Version,
+        control::usb_mux::UsbMuxCtrlMsgN,
+        mux::{DisconnectReason, MuxMsgN},
+    };
+
+    #[test]
+    fn frames_round_trip() {
+        let pkt = Packet {
+            data: PacketData::ImpactReport(ImpactReport { timestamp: 9 }),
+            id: 4,
+        };
+        let disconnected = MuxMsgN::DeviceDisconnected {
+            uuid: [1, 2, 3, 4, 5, 6],
+            reason: DisconnectReason::Remote,
+        };
+        let frames = [
+            Frame {
+                timestamp: Duration::from_micros(1_700_000_000_123_456),
+                direction: Some(Direction::Inbound),
+                dev: Some([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]),
+                transport: Some(TransportMode::Ble),
+                message: Message::Packet(pkt),
+            },
+            Frame {
+                timestamp: Duration::from_micros(2),
+                direction: Some(Direction::Outbound),
+                dev: Some([1, 2, 3, 4, 5, 6]),
+                transport: None,
+                message: Message::Mux(disconnected),
+            },
+            Frame {
+                timestamp: Duration::from_micros(3),
+                direction: None,
+                dev: None,
+                transport: Some(TransportMode::Usb),
+                message: Message::UsbMuxCtrl(UsbMuxCtrlMsgN::ListBonds),
+            },
+            Frame {
+                timestamp: Duration::ZERO,
+                direction: None,
+                dev: None,
+                transport: None,
+                message: Message::Device(DeviceMsg::ReadProp(PropKind::Uuid)),
+            },
+        ];
+        let mut w = PcapngWriter::new(Vec::new()).unwrap();
+        for frame in &frames {
+            w.write(frame).unwrap();
+        }
+        let file = w.into_inner();
+
+        let read: Vec<Frame> = PcapngReader::new(file.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(read.len(), frames.len());
+        for (read, frame) in read.iter().zip(&frames) {
+            assert_eq!(read.timestamp, frame.timestamp);
+            assert_eq!(read.direction, frame.direction);
+            assert_eq!(read.dev, frame.dev);
+            assert_eq!(read.transport, frame.transport);
+            assert_eq!(read.message.interface(), frame.message.interface());
+        }
+        assert!(matches!(
+            &read[0].message,
+            Message::Packet(p) if p.id == 4 && matches!(p.data, PacketData::ImpactReport(r) if r.timestamp == 9)
+        ));
+        assert!(matches!(
+            read[1].message,
+            Message::Mux(MuxMsgN::DeviceDisconnected {
+                uuid: [1, 2, 3, 4, 5, 6],
+                reason: DisconnectReason::Remote,
+            })
+        ));
+        assert!(matches!(
+            read[2].message,
+            Message::UsbMuxCtrl(UsbMuxCtrlMsgN::ListBonds)
+        ));
+        assert!(matches!(
+            read[3].message,
+            Message::Device(DeviceMsg::ReadProp(PropKind::Uuid))
+        ));
+    }
+
+    #[test]
+    fn timestamps_out_of_range_are_format_errors() {
+        assert!(matches!(
+            timestamp(u64::MAX, 0x80),
+            Err(PcapngError::Format("timestamp out of range"))
+        ));
+        assert_eq!(
+            timestamp(3 << 31, 0x80 | 32).unwrap(),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(
+            timestamp(1_500_000, 6).unwrap(),
+            Duration::from_millis(1500)
+        );
+    }
+
+    #[test]
+    fn capture_times_past_the_epoch_range_are_errors() {
+        let header = Header {
+            version: Version::new([0; 3]),
+            product_id: 0,
+            started_unix_us: u64::MAX - 1,
+        };
+        let record = |time_us| Record {
+            time_us,
+            body: RecordBody::Mux(MuxMsgN::RequestDevices),
+        };
+        assert!(Frame::from_capture(&header, record(1)).is_ok());
+        assert!(matches!(
+            Frame::from_capture(&header, record(2)),
+            Err(CaptureError::Decode)
+        ));
+    }
+}
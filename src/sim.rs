@@ -0,0 +1,590 @@
+//! A simulated ATS device, for exercising host code without hardware.
+//!
+//! [`SimDevice`] answers requests the way a device does and, once the host
+//! enables streams with `StreamUpdate`, produces marker, accelerometer, impact
+//! and battery reports from a [`Script`] of where the gun points and when it
+//! fires. It keeps its own clock: [`SimDevice::advance`] moves simulated time
+//! forward and queues the reports that fall due, so runs are deterministic.
+//! It implements [`Transport`], so a [`Client`](crate::client::Client) can
+//! talk to it directly, and its packets can be fed to a dongle
+//! [`Emulator`](crate::mux::emulator::Emulator) through an injector.
+
+use core::convert::Infallible;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+    vec::Vec,
+};
+
+use nalgebra::{Isometry3, Point2, Vector3};
+
+use crate::{
+    AccelConfig, AccelReport, BatteryReport, Capabilities, CombinedMarkersReport, ConfigKind,
+    GeneralConfig, GyroConfig, ImpactReport, Packet, PacketData, PacketType, ProductId, PropKind,
+    Props, StreamUpdate, StreamUpdateAction, Version, client::Transport,
+    control::device::TransportMode, mux::Uuid,
+};
+
+/// Markers on the screen, in screen units.
+const MARKERS: [[f32; 2]; 6] = [
+    [0.05, 0.05],
+    [0.5, 0.05],
+    [0.95, 0.05],
+    [0.05, 0.95],
+    [0.5, 0.95],
+    [0.95, 0.95],
+];
+/// Image coordinates are 12 bits.
+const IMAGE_MAX: f32 = 4095.0;
+/// Image units per screen unit for the near and wide field cameras.
+const NF_SCALE: f32 = 4096.0;
+const WF_SCALE: f32 = 1400.0;
+/// Radians the gun turns to sweep across the screen horizontally and
+/// vertically.
+const FOV_X: f32 = 0.6;
+const FOV_Y: f32 = 0.35;
+const GRAVITY: f32 = 9.80665;
+/// Recoil along -x for a short while after each shot.
+const RECOIL_ACCEL: f32 = 5.0 * GRAVITY;
+const RECOIL: Duration = Duration::from_millis(20);
+
+const MARKERS_PERIOD: Duration = Duration::from_millis(10);
+const BATTERY_PERIOD: Duration = Duration::from_secs(1);
+
+/// Where the gun points over time and when it fires.
+///
+/// Aim points are in screen units: `(0, 0)` is the top left corner of the
+/// screen and `(1, 1)` the bottom right. Between keyframes the aim moves in a
+/// straight line; before the first and after the last it holds still.
+#[derive(Clone, Debug, Default)]
+pub struct Script {
+    keyframes: Vec<(Duration, Point2<f32>)>,
+    shots: Vec<Duration>,
+}
+
+impl Script {
+    /// Aims at the center of the screen and never fires.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn aim_at(mut self, at: Duration, aim: Point2<f32>) -> Self {
+        let i = self.keyframes.partition_point(|(t, _)| *t <= at);
+        self.keyframes.insert(i, (at, aim));
+        self
+    }
+
+    pub fn shot(mut self, at: Duration) -> Self {
+        let i = self.shots.partition_point(|t| *t <= at);
+        self.shots.insert(i, at);
+        self
+    }
+
+    pub fn aim(&self, t: Duration) -> Point2<f32> {
+        let i = self.keyframes.partition_point(|(at, _)| *at <= t);
+        let prev = i.checked_sub(1).map(|i| self.keyframes[i]);
+        match (prev, self.keyframes.get(i)) {
+            (None, None) => Point2::new(0.5, 0.5),
+            (Some((_, a)), None) | (None, Some(&(_, a))) => a,
+            (Some((t0, a)), Some(&(t1, b))) => {
+                let f = (t - t0).as_secs_f32() / (t1 - t0).as_secs_f32();
+                a + (b - a) * f
+            }
+        }
+    }
+
+    pub fn shots(&self) -> &[Duration] {
+        &self.shots
+    }
+
+    /// The time of the last keyframe or shot.
+    pub fn end(&self) -> Duration {
+        let last_key = self.keyframes.last().map(|(t, _)| *t);
+        last_key.max(self.shots.last().copied()).unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Stream {
+    ty: PacketType,
+    /// Reports carry the id of the `StreamUpdate` that enabled them.
+    id: u8,
+    next: Duration,
+}
+
+/// A simulated device.
+///
+/// Handles `ReadProp`, `ReadConfig`, `WriteConfig`, `FlashSettings`,
+/// `StreamUpdate`, `WriteMode`, `ReadVersion`, `SetDeviceName` and
+/// `ReadCapabilities`, and streams `CombinedMarkersReport`, `AccelReport`,
+/// `ImpactReport` and `BatteryReport`. Camera models have no default and are
+/// only answered once written. Report timestamps are microseconds of
+/// simulated time.
+#[derive(Clone, Debug)]
+pub struct SimDevice {
+    uuid: Uuid,
+    product_id: ProductId,
+    name: heapless::String<32>,
+    version: Version,
+    config: Vec<GeneralConfig>,
+    flashed: Vec<GeneralConfig>,
+    script: Script,
+    streams: Vec<Stream>,
+    now: Duration,
+    last_impact: Option<Duration>,
+    outbox: VecDeque<Packet>,
+}
+
+impl SimDevice {
+    pub fn new(uuid: Uuid, product_id: ProductId, script: Script) -> Self {
+        let config = Vec::from([
+            GeneralConfig::ImpactThreshold(20),
+            GeneralConfig::SuppressMs(100),
+            GeneralConfig::AccelConfig(AccelConfig::default()),
+            GeneralConfig::GyroConfig(GyroConfig::default()),
+            GeneralConfig::StereoIso(Isometry3::identity()),
+        ]);
+        let mut name = heapless::String::new();
+        let _ = name.push_str("sim");
+        Self {
+            uuid,
+            product_id,
+            name,
+            version: Version::new([0, 0, 0]),
+            flashed: config.clone(),
+            config,
+            script,
+            streams: Vec::new(),
+            now: Duration::ZERO,
+            last_impact: None,
+            outbox: VecDeque::new(),
+        }
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    /// Simulated time since the device started.
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    pub fn config(&self, kind: ConfigKind) -> Option<&GeneralConfig> {
+        self.config.iter().find(|c| c.kind() == kind)
+    }
+
+    /// The config as last saved with `FlashSettings`.
+    pub fn flashed_config(&self, kind: ConfigKind) -> Option<&GeneralConfig> {
+        self.flashed.iter().find(|c| c.kind() == kind)
+    }
+
+    /// Sets a config as `WriteConfig` would, without a reply.
+    pub fn set_config(&mut self, config: GeneralConfig) {
+        match self.config.iter_mut().find(|c| c.kind() == config.kind()) {
+            Some(c) => *c = config,
+            None => self.config.push(config),
+        }
+    }
+
+    /// Whether the host has enabled the stream of `ty` reports.
+    pub fn is_streaming(&self, ty: PacketType) -> bool {
+        self.stream_index(ty).is_some()
+    }
+
+    /// Powers the device off and on: streams stop and unflashed config is
+    /// lost.
+    pub fn reboot(&mut self) {
+        self.config = self.flashed.clone();
+        self.streams.clear();
+    }
+
+    /// What the device reports in answer to `ReadCapabilities`.
+    pub fn capabilities(&self) -> Capabilities {
+        let mut caps = Capabilities::default();
+        for ty in [
+            PacketType::ReadProp(),
+            PacketType::ReadConfig(),
+            PacketType::WriteConfig(),
+            PacketType::FlashSettings(),
+            PacketType::StreamUpdate(),
+            PacketType::WriteMode(),
+            PacketType::ReadVersion(),
+            PacketType::SetDeviceName(),
+            PacketType::ReadCapabilities(),
+        ] {
+            caps.insert_packet_type(ty);
+        }
+        for ty in [
+            PacketType::CombinedMarkersReport(),
+            PacketType::AccelReport(),
+            PacketType::ImpactReport(),
+            PacketType::BatteryReport(),
+        ] {
+            caps.insert_stream(ty);
+        }
+        for c in &self.config {
+            caps.insert_config_kind(c.kind());
+        }
+        caps.insert_transport(TransportMode::Usb);
+        caps
+    }
+
+    /// Handles a packet from the host, queueing the reply if there is one.
+    pub fn handle(&mut self, pkt: Packet) {
+        let reply = match pkt.data {
+            PacketData::ReadProp(kind) => Some(PacketData::ReadPropResponse(self.prop(kind))),
+            PacketData::ReadConfig(kind) => self
+                .config(kind)
+                .cloned()
+                .map(PacketData::ReadConfigResponse),
+            PacketData::WriteConfig(config) => {
+                self.set_config(config);
+                Some(PacketData::Ack())
+            }
+            PacketData::FlashSettings() => {
+                self.flashed = self.config.clone();
+                Some(PacketData::Ack())
+            }
+            PacketData::WriteMode(_) => Some(PacketData::Ack()),
+            PacketData::StreamUpdate(update) => {
+                self.update_stream(pkt.id, update);
+                None
+            }
+            PacketData::ReadVersion() => Some(PacketData::ReadVersionResponse(self.version)),
+            PacketData::SetDeviceName(name) => {
+                self.name = name;
+                Some(PacketData::SetDeviceNameResponse(Ok(())))
+            }
+            PacketData::ReadCapabilities() => {
+                Some(PacketData::ReadCapabilitiesResponse(self.capabilities()))
+            }
+            _ => None,
+        };
+        if let Some(data) = reply {
+            self.outbox.push_back(Packet { id: pkt.id, data });
+        }
+    }
+
+    /// Moves simulated time forward by `dt` and queues, in time order, the
+    /// reports of enabled streams that fall due.
+    pub fn advance(&mut self, dt: Duration) {
+        let end = self.now + dt;
+        let mut due = Vec::new();
+        for i in 0..self.streams.len() {
+            let Some(period) = self.period(self.streams[i].ty) else {
+                continue;
+            };
+            while self.streams[i].next < end {
+                let Stream { ty, id, next } = self.streams[i];
+                due.push((
+                    next,
+                    Packet {
+                        id,
+                        data: self.report(ty, next),
+                    },
+                ));
+                self.streams[i].next += period;
+            }
+        }
+        if let Some(i) = self.stream_index(PacketType::ImpactReport()) {
+            let id = self.streams[i].id;
+            let suppress = Duration::from_millis(match self.config(ConfigKind::SuppressMs) {
+                Some(GeneralConfig::SuppressMs(ms)) => u64::from(*ms),
+                _ => 0,
+            });
+            for &t in self.script.shots() {
+                let suppressed = self.last_impact.is_some_and(|last| t < last + suppress);
+                if t < self.now || t >= end || suppressed {
+                    continue;
+                }
+                self.last_impact = Some(t);
+                let data = PacketData::ImpactReport(ImpactReport {
+                    timestamp: timestamp(t),
+                });
+                due.push((t, Packet { id, data }));
+            }
+        }
+        due.sort_by_key(|(t, _)| *t);
+        self.outbox.extend(due.into_iter().map(|(_, pkt)| pkt));
+        self.now = end;
+    }
+
+    /// The next packet for the host.
+    pub fn recv(&mut self) -> Option<Packet> {
+        self.outbox.pop_front()
+    }
+
+    fn prop(&self, kind: PropKind) -> Props {
+        match kind {
+            PropKind::Uuid => Props::Uuid(self.uuid),
+            PropKind::ProductId => Props::ProductId(self.product_id as u16),
+            PropKind::Name => Props::Name(self.name.clone()),
+            PropKind::Version => Props::Version(self.version),
+        }
+    }
+
+    fn stream_index(&self, ty: PacketType) -> Option<usize> {
+        let ty = u8::from(ty);
+        self.streams.iter().position(|s| u8::from(s.ty) == ty)
+    }
+
+    fn update_stream(&mut self, id: u8, update: StreamUpdate) {
+        let existing = self.stream_index(update.packet_id);
+        match update.action {
+            StreamUpdateAction::Enable if self.is_streamable(update.packet_id) => {
+                let stream = Stream {
+                    ty: update.packet_id,
+                    id,
+                    next: self.now,
+                };
+                match existing {
+                    Some(i) => self.streams[i] = stream,
+                    None => self.streams.push(stream),
+                }
+            }
+            StreamUpdateAction::Enable => {}
+            StreamUpdateAction::Disable => {
+                if let Some(i) = existing {
+                    self.streams.remove(i);
+                }
+            }
+            StreamUpdateAction::DisableAll => self.streams.clear(),
+        }
+    }
+
+    fn is_streamable(&self, ty: PacketType) -> bool {
+        matches!(ty, PacketType::ImpactReport()) || self.period(ty).is_some()
+    }
+
+    /// The period of a stream that reports at a fixed rate.
+    fn period(&self, ty: PacketType) -> Option<Duration> {
+        match ty {
+            PacketType::CombinedMarkersReport() => Some(MARKERS_PERIOD),
+            PacketType::AccelReport() => {
+                let odr = match self.config(ConfigKind::AccelConfig) {
+                    Some(GeneralConfig::AccelConfig(c)) => c.accel_odr.max(1),
+                    _ => AccelConfig::default().accel_odr,
+                };
+                Some(Duration::from_secs(1) / u32::from(odr))
+            }
+            PacketType::BatteryReport() => Some(BATTERY_PERIOD),
+            _ => None,
+        }
+    }
+
+    fn report(&self, ty: PacketType, t: Duration) -> PacketData {
+        match ty {
+            PacketType::CombinedMarkersReport() => {
+                let aim = self.script.aim(t);
+                PacketData::CombinedMarkersReport(CombinedMarkersReport {
+                    nf_points: project(aim, NF_SCALE),
+                    wf_points: project(aim, WF_SCALE),
+                })
+            }
+            PacketType::AccelReport() => PacketData::AccelReport(self.accel_report(t)),
+            PacketType::BatteryReport() => {
+                let drained = (t.as_secs() / 60).min(100) as u8;
+                PacketData::BatteryReport(BatteryReport {
+                    percent: 100 - drained,
+                    charging: false,
+                })
+            }
+            _ => unreachable!("{ty:?} is not a periodic stream"),
+        }
+    }
+
+    /// With the gun level, gravity reads along +z. The gyro reads the pitch
+    /// rate about x and the yaw rate about y.
+    fn accel_report(&self, t: Duration) -> AccelReport {
+        const H: Duration = Duration::from_millis(1);
+        let (t0, t1) = (t.saturating_sub(H), t + H);
+        let rate = (self.script.aim(t1) - self.script.aim(t0)) / (t1 - t0).as_secs_f32();
+        let recoil = self
+            .script
+            .shots()
+            .iter()
+            .any(|&s| s <= t && t < s + RECOIL);
+        let accel_x = if recoil { -RECOIL_ACCEL } else { 0.0 };
+        AccelReport {
+            timestamp: timestamp(t),
+            accel: Vector3::new(accel_x, 0.0, GRAVITY),
+            gyro: Vector3::new(-rate.y * FOV_Y, -rate.x * FOV_X, 0.0),
+        }
+    }
+}
+
+/// Where the markers appear in a camera's image when the gun aims at `aim`.
+/// Visible markers fill the first slots and the rest stay at the origin.
+fn project(aim: Point2<f32>, scale: f32) -> [Point2<u16>; 16] {
+    let center = IMAGE_MAX / 2.0;
+    let visible = MARKERS.iter().filter_map(|&[x, y]| {
+        let p = Point2::new(center + (x - aim.x) * scale, center + (y - aim.y) * scale);
+        let inside = |v: f32| (0.0..=IMAGE_MAX).contains(&v);
+        (inside(p.x) && inside(p.y)).then(|| Point2::new(p.x.round() as u16, p.y.round() as u16))
+    });
+    let mut points = [Point2::new(0, 0); 16];
+    for (slot, p) in points.iter_mut().zip(visible) {
+        *slot = p;
+    }
+    points
+}
+
+fn timestamp(t: Duration) -> u32 {
+    t.as_micros() as u32
+}
+
+/// Sends are handled immediately and `recv` never waits: it returns
+/// `Ok(None)` once the queued packets run out, whatever the deadline.
+impl Transport for SimDevice {
+    type Error = Infallible;
+
+    fn send(&mut self, pkt: &Packet) -> Result<(), Infallible> {
+        self.handle(pkt.clone());
+        Ok(())
+    }
+
+    fn recv(&mut self, _deadline: Instant) -> Result<Option<Packet>, Infallible> {
+        Ok(SimDevice::recv(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Client;
+
+    fn sim(script: Script) -> SimDevice {
+        SimDevice::new([1; 6], ProductId::AtsLite, script)
+    }
+
+    fn enable(sim: &mut SimDevice, id: u8, ty: PacketType) {
+        sim.handle(Packet {
+            data: PacketData::StreamUpdate(StreamUpdate {
+                packet_id: ty,
+                action: StreamUpdateAction::Enable,
+            }),
+            id,
+        });
+    }
+
+    fn drain(sim: &mut SimDevice) -> Vec<Packet> {
+        core::iter::from_fn(|| sim.recv()).collect()
+    }
+
+    #[test]
+    fn client_handshakes_and_calls() {
+        let mut sim = sim(Script::new());
+        let mut client = Client::new(Duration::from_millis(100));
+        let negotiated = client.handshake(&mut sim, |_| {}).unwrap();
+        assert_eq!(negotiated.features, sim.capabilities());
+        assert!(
+            negotiated
+                .features
+                .supports_stream(PacketType::ImpactReport())
+        );
+
+        let resp = client
+            .call(&mut sim, PacketData::ReadProp(PropKind::Uuid), |_| {})
+            .unwrap();
+        assert!(matches!(
+            resp.data,
+            PacketData::ReadPropResponse(Props::Uuid(uuid)) if uuid == [1; 6]
+        ));
+        assert_eq!(client.outstanding(), 0);
+    }
+
+    #[test]
+    fn streams_report_at_their_periods() {
+        let mut sim = sim(Script::new());
+        enable(&mut sim, 3, PacketType::AccelReport());
+        enable(&mut sim, 4, PacketType::BatteryReport());
+        assert!(sim.is_streaming(PacketType::AccelReport()));
+
+        sim.advance(Duration::from_millis(45));
+        let pkts = drain(&mut sim);
+        let accel: Vec<u32> = pkts
+            .iter()
+            .filter_map(|p| match &p.data {
+                PacketData::AccelReport(r) => {
+                    assert_eq!(p.id, 3);
+                    Some(r.timestamp)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(accel, [0, 10_000, 20_000, 30_000, 40_000]);
+        let battery = pkts.iter().filter(|p| p.id == 4).count();
+        assert_eq!(battery, 1);
+        assert_eq!(pkts.len(), 6);
+
+        // The next second brings one more battery report.
+        sim.advance(Duration::from_secs(1));
+        let pkts = drain(&mut sim);
+        assert_eq!(pkts.iter().filter(|p| p.id == 4).count(), 1);
+        assert_eq!(pkts.iter().filter(|p| p.id == 3).count(), 100);
+    }
+
+    #[test]
+    fn impacts_are_suppressed() {
+        let ms = Duration::from_millis;
+        let script = Script::new()
+            .shot(ms(0))
+            .shot(ms(50))
+            .shot(ms(150))
+            .shot(ms(200));
+        let mut sim = sim(script);
+        assert_eq!(
+            sim.config(ConfigKind::SuppressMs),
+            Some(&GeneralConfig::SuppressMs(100))
+        );
+        enable(&mut sim, 9, PacketType::ImpactReport());
+
+        sim.advance(ms(300));
+        let impacts: Vec<(u8, u32)> = drain(&mut sim)
+            .into_iter()
+            .map(|p| match p.data {
+                PacketData::ImpactReport(r) => (p.id, r.timestamp),
+                data => panic!("unexpected {data:?}"),
+            })
+            .collect();
+        assert_eq!(impacts, [(9, 0), (9, 150_000)]);
+    }
+
+    #[test]
+    fn reboot_keeps_only_flashed_config() {
+        let mut sim = sim(Script::new());
+        let write = |sim: &mut SimDevice, config| {
+            sim.handle(Packet {
+                data: PacketData::WriteConfig(config),
+                id: 0,
+            });
+        };
+        write(&mut sim, GeneralConfig::SuppressMs(5));
+        sim.handle(Packet {
+            data: PacketData::FlashSettings(),
+            id: 0,
+        });
+        write(&mut sim, GeneralConfig::SuppressMs(7));
+        write(&mut sim, GeneralConfig::ImpactThreshold(1));
+        enable(&mut sim, 1, PacketType::BatteryReport());
+        assert_eq!(
+            sim.config(ConfigKind::SuppressMs),
+            Some(&GeneralConfig::SuppressMs(7))
+        );
+
+        sim.reboot();
+        assert_eq!(
+            sim.config(ConfigKind::SuppressMs),
+            Some(&GeneralConfig::SuppressMs(5))
+        );
+        assert_eq!(
+            sim.config(ConfigKind::ImpactThreshold),
+            Some(&GeneralConfig::ImpactThreshold(20))
+        );
+        assert!(!sim.is_streaming(PacketType::BatteryReport()));
+        drain(&mut sim);
+        sim.advance(Duration::from_secs(2));
+        assert!(sim.recv().is_none());
+    }
+}
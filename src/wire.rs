@@ -6,6 +6,10 @@ use ats_common::ocv_types::{
 use nalgebra::ComplexField;
 use opencv_ros_camera::RosOpenCvIntrinsics;
 
+use core::mem::MaybeUninit;
+
+use crate::{Error, Parse, Serialize, parse_f32s, push, push_f32s, take};
+
 #[cfg(feature = "minicbor")]
 use minicbor::{CborLen, Decode, Encode};
 
@@ -95,6 +99,23 @@ pub struct CameraCalibrationParams {
     pub dist_coeffs: [f32; 5],
 }
 
+impl Parse for CameraCalibrationParams {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            camera_matrix: parse_f32s(bytes)?,
+            dist_coeffs: parse_f32s(bytes)?,
+        })
+    }
+}
+
+impl Serialize for CameraCalibrationParams {
+    const SIZE: usize = 56;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push_f32s(buf, &self.camera_matrix);
+        push_f32s(buf, &self.dist_coeffs);
+    }
+}
+
 impl From<CameraCalibrationParams> for RosOpenCvIntrinsics<f32> {
     fn from(value: CameraCalibrationParams) -> Self {
         MinimalCameraCalibrationParams {
@@ -140,6 +161,23 @@ impl Default for StereoCalibrationParams {
     }
 }
 
+impl Parse for StereoCalibrationParams {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            r: parse_f32s(bytes)?,
+            t: parse_f32s(bytes)?,
+        })
+    }
+}
+
+impl Serialize for StereoCalibrationParams {
+    const SIZE: usize = 48;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push_f32s(buf, &self.r);
+        push_f32s(buf, &self.t);
+    }
+}
+
 impl From<StereoCalibrationParams> for nalgebra::Isometry3<f32> {
     fn from(value: StereoCalibrationParams) -> Self {
         MinimalStereoCalibrationParams {
@@ -220,6 +258,32 @@ pub(super) struct AccelReport {
     gyro: [i16; 3],
 }
 
+impl Parse for AccelReport {
+    fn parse(bytes: &mut &[u8]) -> Result<Self, Error> {
+        let timestamp = u32::from_le_bytes(take(bytes)?);
+        let mut axes = [0; 6];
+        for v in &mut axes {
+            *v = i16::from_le_bytes(take(bytes)?);
+        }
+        let [ax, ay, az, gx, gy, gz] = axes;
+        Ok(Self {
+            timestamp,
+            accel: [ax, ay, az],
+            gyro: [gx, gy, gz],
+        })
+    }
+}
+
+impl Serialize for AccelReport {
+    const SIZE: usize = 16;
+    fn serialize(&self, buf: &mut &mut [MaybeUninit<u8>]) {
+        push(buf, &self.timestamp.to_le_bytes());
+        for v in self.accel.iter().chain(&self.gyro) {
+            push(buf, &v.to_le_bytes());
+        }
+    }
+}
+
 // accel: x, y, z, 2048 = 1g
 // gyro: x, y, z, 16.4 = 1dps
 impl From<super::AccelReport> for AccelReport {
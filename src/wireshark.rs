@@ -0,0 +1,1240 @@
+//! Generates a Wireshark dissector for [`Packet`](crate::Packet)s.
+//!
+//! [`lua_dissector`] emits a Lua plugin that decodes the `[words_le, ty, id]`
+//! header and every payload as the [`Serialize`] impls lay it out, including
+//! the 12-bit packed marker points and the [`MotData`](crate::MotData)
+//! bitfields. The packet id table comes from `TryFrom<u8>` for [`PacketType`],
+//! enum names from the enums themselves, and each payload layout is checked
+//! against its type's [`Serialize::SIZE`] at compile time, so the dissector
+//! follows the crate rather than a copy of it.
+//!
+//! The plugin registers for link type 147 (`LINKTYPE_USER0`), the one pcapng
+//! exports use for packets, and can be picked for USB bulk transfers with
+//! Decode As. Regenerate it with
+//! `cargo run --example wireshark_dissector > protodongers.lua`.
+
+use core::fmt::{self, Debug, Display, Write};
+use std::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use static_assertions::const_assert_eq;
+
+use crate::{
+    AccelConfig, AccelReport, BatteryReport, Capabilities, CombinedMarkersReport, ConfigKind,
+    GeneralConfig, GyroConfig, ImpactReport, Mode, MotData, ObjectReport, PacketType, Parse,
+    PocMarkersReport, Port, PropKind, Props, ReadRegisterResponse, Register, Serialize,
+    StreamUpdate, StreamUpdateAction, VendorData, Version, WriteRegister, wire,
+};
+
+/// The Lua source of the dissector.
+pub fn lua_dissector() -> String {
+    LuaDissector.to_string()
+}
+
+struct LuaDissector;
+
+/// A table of names for the values of a byte.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Values {
+    PacketType,
+    Port,
+    ConfigKind,
+    PropKind,
+    StreamUpdateAction,
+    Mode,
+    Result,
+}
+
+impl Values {
+    const ALL: [Self; 7] = [
+        Self::PacketType,
+        Self::Port,
+        Self::ConfigKind,
+        Self::PropKind,
+        Self::StreamUpdateAction,
+        Self::Mode,
+        Self::Result,
+    ];
+
+    fn table(self) -> &'static str {
+        match self {
+            Self::PacketType => "vs_packet_type",
+            Self::Port => "vs_port",
+            Self::ConfigKind => "vs_config_kind",
+            Self::PropKind => "vs_prop_kind",
+            Self::StreamUpdateAction => "vs_stream_update_action",
+            Self::Mode => "vs_mode",
+            Self::Result => "vs_result",
+        }
+    }
+
+    fn name(self, n: u8) -> Option<String> {
+        match self {
+            Self::PacketType => PacketType::try_from(n).ok().map(|ty| match ty {
+                PacketType::Vendor(_) => "Vendor".into(),
+                ty => variant_name(ty),
+            }),
+            Self::Port => Port::try_from(n).ok().map(variant_name),
+            Self::ConfigKind => ConfigKind::try_from(n).ok().map(variant_name),
+            Self::PropKind => PropKind::try_from(n).ok().map(variant_name),
+            Self::StreamUpdateAction => StreamUpdateAction::try_from(n).ok().map(variant_name),
+            Self::Mode => Mode::try_from(n).ok().map(variant_name),
+            Self::Result => <Result<(), ()>>::parse(&mut &[n, 0][..])
+                .ok()
+                .map(|r| if r.is_ok() { "Ok" } else { "Err" }.into()),
+        }
+    }
+}
+
+fn variant_name(v: impl Debug) -> String {
+    let name = format!("{v:?}");
+    name.strip_suffix("()").unwrap_or(&name).into()
+}
+
+/// One field of a payload layout.
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    /// A little-endian unsigned integer of `len` bytes.
+    Uint {
+        name: &'static str,
+        len: usize,
+        hex: bool,
+        values: Option<Values>,
+    },
+    /// Masked fields sharing the same `len` bytes.
+    Bits {
+        len: usize,
+        fields: &'static [(&'static str, u64)],
+    },
+    /// A little-endian `i16`, shown converted as well when it has a scale of
+    /// raw units per unit.
+    I16 {
+        name: &'static str,
+        scale: Option<(f32, &'static str)>,
+    },
+    /// `count` little-endian `f32`s.
+    F32 {
+        name: &'static str,
+        count: usize,
+    },
+    Bool(&'static str),
+    Bytes(&'static str, usize),
+    /// A zero-padded string of `len` bytes.
+    Str(&'static str, usize),
+    Pad(usize),
+    /// `count` points with 12-bit `x` and `y`, packed into 3 bytes.
+    Points(&'static str, usize),
+    Repeat(&'static str, usize, &'static Layout),
+    /// `len` bytes laid out according to the byte at offset `on` of the
+    /// enclosing layout.
+    Switch {
+        on: usize,
+        len: usize,
+        cases: &'static [Case],
+    },
+}
+
+impl Field {
+    const fn size(&self) -> usize {
+        match *self {
+            Self::Uint { len, .. } | Self::Bits { len, .. } => len,
+            Self::I16 { .. } => 2,
+            Self::F32 { count, .. } => 4 * count,
+            Self::Bool(_) => 1,
+            Self::Bytes(_, len) | Self::Str(_, len) | Self::Pad(len) => len,
+            Self::Points(_, count) => 3 * count,
+            Self::Repeat(_, count, layout) => count * layout.size(),
+            Self::Switch { len, .. } => len,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Case {
+    value: u8,
+    name: &'static str,
+    fields: &'static [Field],
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Layout {
+    name: &'static str,
+    fields: &'static [Field],
+}
+
+impl Layout {
+    const fn size(&self) -> usize {
+        let mut size = 0;
+        let mut i = 0;
+        while i < self.fields.len() {
+            size += self.fields[i].size();
+            i += 1;
+        }
+        size
+    }
+}
+
+const fn uint(name: &'static str, len: usize) -> Field {
+    Field::Uint {
+        name,
+        len,
+        hex: false,
+        values: None,
+    }
+}
+
+const fn hex(name: &'static str, len: usize) -> Field {
+    Field::Uint {
+        name,
+        len,
+        hex: true,
+        values: None,
+    }
+}
+
+const fn named(name: &'static str, values: Values) -> Field {
+    Field::Uint {
+        name,
+        len: 1,
+        hex: matches!(values, Values::PacketType),
+        values: Some(values),
+    }
+}
+
+const fn f32(name: &'static str) -> Field {
+    Field::F32 { name, count: 1 }
+}
+
+const WRITE_REGISTER: Layout = Layout {
+    name: "write_register",
+    fields: &[
+        named("port", Values::Port),
+        uint("bank", 1),
+        hex("address", 1),
+        hex("data", 1),
+    ],
+};
+
+const REGISTER: Layout = Layout {
+    name: "register",
+    fields: &[
+        named("port", Values::Port),
+        uint("bank", 1),
+        hex("address", 1),
+        Field::Pad(1),
+    ],
+};
+
+const READ_REGISTER_RESPONSE: Layout = Layout {
+    name: "read_register_response",
+    fields: &[
+        uint("bank", 1),
+        hex("address", 1),
+        hex("data", 1),
+        Field::Pad(1),
+    ],
+};
+
+const CONFIG_KIND: Layout = Layout {
+    name: "config_kind",
+    fields: &[named("kind", Values::ConfigKind), Field::Pad(1)],
+};
+
+const ACCEL_CONFIG: Layout = Layout {
+    name: "accel_config",
+    fields: &[
+        uint("accel_odr", 2),
+        f32("b_x"),
+        f32("b_y"),
+        f32("b_z"),
+        f32("s_x"),
+        f32("s_y"),
+        f32("s_z"),
+    ],
+};
+
+const GYRO_CONFIG: Layout = Layout {
+    name: "gyro_config",
+    fields: &[f32("b_x"), f32("b_y"), f32("b_z")],
+};
+
+const CAMERA_CALIBRATION: Layout = Layout {
+    name: "camera_calibration",
+    fields: &[
+        Field::F32 {
+            name: "camera_matrix",
+            count: 9,
+        },
+        Field::F32 {
+            name: "dist_coeffs",
+            count: 5,
+        },
+    ],
+};
+
+const STEREO_CALIBRATION: Layout = Layout {
+    name: "stereo_calibration",
+    fields: &[
+        Field::F32 {
+            name: "r",
+            count: 9,
+        },
+        Field::F32 {
+            name: "t",
+            count: 3,
+        },
+    ],
+};
+
+const GENERAL_CONFIG: Layout = Layout {
+    name: "general_config",
+    fields: &[
+        named("kind", Values::ConfigKind),
+        Field::Pad(1),
+        Field::Switch {
+            on: 0,
+            len: 56,
+            cases: &[
+                Case {
+                    value: ConfigKind::ImpactThreshold as u8,
+                    name: "impact_threshold",
+                    fields: &[uint("impact_threshold", 1)],
+                },
+                Case {
+                    value: ConfigKind::SuppressMs as u8,
+                    name: "suppress_ms",
+                    fields: &[uint("suppress_ms", 1)],
+                },
+                Case {
+                    value: ConfigKind::AccelConfig as u8,
+                    name: "accel_config",
+                    fields: ACCEL_CONFIG.fields,
+                },
+                Case {
+                    value: ConfigKind::GyroConfig as u8,
+                    name: "gyro_config",
+                    fields: GYRO_CONFIG.fields,
+                },
+                Case {
+                    value: ConfigKind::CameraModelNf as u8,
+                    name: "camera_model_nf",
+                    fields: CAMERA_CALIBRATION.fields,
+                },
+                Case {
+                    value: ConfigKind::CameraModelWf as u8,
+                    name: "camera_model_wf",
+                    fields: CAMERA_CALIBRATION.fields,
+                },
+                Case {
+                    value: ConfigKind::StereoIso as u8,
+                    name: "stereo_iso",
+                    fields: STEREO_CALIBRATION.fields,
+                },
+            ],
+        },
+    ],
+};
+
+const PROP_KIND: Layout = Layout {
+    name: "prop_kind",
+    fields: &[named("kind", Values::PropKind), Field::Pad(1)],
+};
+
+const VERSION: Layout = Layout {
+    name: "version",
+    fields: &[
+        uint("protocol_major", 2),
+        uint("protocol_minor", 2),
+        uint("protocol_patch", 2),
+        uint("firmware_major", 2),
+        uint("firmware_minor", 2),
+        uint("firmware_patch", 2),
+    ],
+};
+
+/// `heapless::String<32>`
+const NAME: Layout = Layout {
+    name: "name",
+    fields: &[uint("len", 1), Field::Str("name", 32), Field::Pad(1)],
+};
+
+const PROPS: Layout = Layout {
+    name: "props",
+    fields: &[
+        named("kind", Values::PropKind),
+        Field::Pad(1),
+        Field::Switch {
+            on: 0,
+            len: 34,
+            cases: &[
+                Case {
+                    value: PropKind::Uuid as u8,
+                    name: "uuid",
+                    fields: &[Field::Bytes("uuid", 6)],
+                },
+                Case {
+                    value: PropKind::ProductId as u8,
+                    name: "product_id",
+                    fields: &[hex("product_id", 2)],
+                },
+                Case {
+                    value: PropKind::Name as u8,
+                    name: "name",
+                    fields: NAME.fields,
+                },
+                Case {
+                    value: PropKind::Version as u8,
+                    name: "version",
+                    fields: VERSION.fields,
+                },
+            ],
+        },
+    ],
+};
+
+const MOT_DATA: Layout = Layout {
+    name: "mot_data",
+    fields: &[
+        uint("area", 2),
+        Field::Bits {
+            len: 2,
+            fields: &[("cx", 0x0fff)],
+        },
+        Field::Bits {
+            len: 2,
+            fields: &[("cy", 0x0fff)],
+        },
+        uint("avg_brightness", 1),
+        uint("max_brightness", 1),
+        Field::Bits {
+            len: 1,
+            fields: &[("radius", 0x0f), ("range", 0xf0)],
+        },
+        Field::Bits {
+            len: 1,
+            fields: &[("boundary_left", 0x7f)],
+        },
+        Field::Bits {
+            len: 1,
+            fields: &[("boundary_right", 0x7f)],
+        },
+        Field::Bits {
+            len: 1,
+            fields: &[("boundary_up", 0x7f)],
+        },
+        Field::Bits {
+            len: 1,
+            fields: &[("boundary_down", 0x7f)],
+        },
+        uint("aspect_ratio", 1),
+        uint("vx", 1),
+        uint("vy", 1),
+    ],
+};
+
+const OBJECT_REPORT: Layout = Layout {
+    name: "object_report",
+    fields: &[
+        uint("timestamp", 4),
+        Field::Repeat("mot_data_nf", 16, &MOT_DATA),
+        Field::Repeat("mot_data_wf", 16, &MOT_DATA),
+        uint("format", 1),
+        Field::Pad(1),
+    ],
+};
+
+const COMBINED_MARKERS_REPORT: Layout = Layout {
+    name: "combined_markers_report",
+    fields: &[
+        Field::Points("nf_points", 16),
+        Field::Points("wf_points", 16),
+    ],
+};
+
+const POC_MARKERS_REPORT: Layout = Layout {
+    name: "poc_markers_report",
+    fields: &[Field::Points("points", 16)],
+};
+
+// accel: 2048 = 1g, gyro: 16.4 = 1dps
+const ACCEL_REPORT: Layout = Layout {
+    name: "accel_report",
+    fields: &[
+        uint("timestamp", 4),
+        Field::I16 {
+            name: "accel_x",
+            scale: Some((2048.0, "g")),
+        },
+        Field::I16 {
+            name: "accel_y",
+            scale: Some((2048.0, "g")),
+        },
+        Field::I16 {
+            name: "accel_z",
+            scale: Some((2048.0, "g")),
+        },
+        Field::I16 {
+            name: "gyro_x",
+            scale: Some((16.4, "dps")),
+        },
+        Field::I16 {
+            name: "gyro_y",
+            scale: Some((16.4, "dps")),
+        },
+        Field::I16 {
+            name: "gyro_z",
+            scale: Some((16.4, "dps")),
+        },
+    ],
+};
+
+const IMPACT_REPORT: Layout = Layout {
+    name: "impact_report",
+    fields: &[uint("timestamp", 4)],
+};
+
+const STREAM_UPDATE: Layout = Layout {
+    name: "stream_update",
+    fields: &[
+        named("packet_id", Values::PacketType),
+        named("action", Values::StreamUpdateAction),
+    ],
+};
+
+const MODE: Layout = Layout {
+    name: "mode",
+    fields: &[named("mode", Values::Mode), Field::Pad(1)],
+};
+
+const VENDOR_DATA: Layout = Layout {
+    name: "vendor_data",
+    fields: &[uint("len", 1), Field::Bytes("data", 98), Field::Pad(1)],
+};
+
+const BATTERY_REPORT: Layout = Layout {
+    name: "battery_report",
+    fields: &[uint("percent", 1), Field::Bool("charging")],
+};
+
+const RESULT: Layout = Layout {
+    name: "result",
+    fields: &[named("result", Values::Result), Field::Pad(1)],
+};
+
+const CAPABILITIES: Layout = Layout {
+    name: "capabilities",
+    fields: &[
+        Field::Bytes("packet_types", 32),
+        Field::Bytes("streams", 32),
+        hex("config_kinds", 4),
+        hex("transports", 1),
+        Field::Bool("control_endpoint"),
+    ],
+};
+
+const_assert_eq!(WRITE_REGISTER.size(), WriteRegister::SIZE);
+const_assert_eq!(REGISTER.size(), Register::SIZE);
+const_assert_eq!(READ_REGISTER_RESPONSE.size(), ReadRegisterResponse::SIZE);
+const_assert_eq!(CONFIG_KIND.size(), ConfigKind::SIZE);
+const_assert_eq!(ACCEL_CONFIG.size(), AccelConfig::SIZE);
+const_assert_eq!(GYRO_CONFIG.size(), GyroConfig::SIZE);
+const_assert_eq!(
+    CAMERA_CALIBRATION.size(),
+    wire::CameraCalibrationParams::SIZE
+);
+const_assert_eq!(
+    STEREO_CALIBRATION.size(),
+    wire::StereoCalibrationParams::SIZE
+);
+const_assert_eq!(GENERAL_CONFIG.size(), GeneralConfig::SIZE);
+const_assert_eq!(PROP_KIND.size(), PropKind::SIZE);
+const_assert_eq!(VERSION.size(), Version::SIZE);
+const_assert_eq!(NAME.size(), <heapless::String<32>>::SIZE);
+const_assert_eq!(PROPS.size(), Props::SIZE);
+const_assert_eq!(MOT_DATA.size(), MotData::SIZE);
+const_assert_eq!(OBJECT_REPORT.size(), ObjectReport::SIZE);
+const_assert_eq!(COMBINED_MARKERS_REPORT.size(), CombinedMarkersReport::SIZE);
+const_assert_eq!(POC_MARKERS_REPORT.size(), PocMarkersReport::SIZE);
+const_assert_eq!(ACCEL_REPORT.size(), AccelReport::SIZE);
+const_assert_eq!(IMPACT_REPORT.size(), ImpactReport::SIZE);
+const_assert_eq!(STREAM_UPDATE.size(), StreamUpdate::SIZE);
+const_assert_eq!(MODE.size(), Mode::SIZE);
+const_assert_eq!(VENDOR_DATA.size(), VendorData::SIZE);
+const_assert_eq!(BATTERY_REPORT.size(), BatteryReport::SIZE);
+const_assert_eq!(RESULT.size(), <Result<(), ()>>::SIZE);
+const_assert_eq!(CAPABILITIES.size(), Capabilities::SIZE);
+
+/// The payload layout of `ty`, or `None` if it has no payload.
+fn payload(ty: PacketType) -> Option<&'static Layout> {
+    use PacketType as T;
+    Some(match ty {
+        T::WriteRegister() => &WRITE_REGISTER,
+        T::ReadRegister() => &REGISTER,
+        T::ReadRegisterResponse() => &READ_REGISTER_RESPONSE,
+        T::WriteConfig() | T::ReadConfigResponse() => &GENERAL_CONFIG,
+        T::ReadConfig() => &CONFIG_KIND,
+        T::ReadProp() => &PROP_KIND,
+        T::ReadPropResponse() => &PROPS,
+        T::ObjectReport() => &OBJECT_REPORT,
+        T::CombinedMarkersReport() => &COMBINED_MARKERS_REPORT,
+        T::PocMarkersReport() => &POC_MARKERS_REPORT,
+        T::AccelReport() => &ACCEL_REPORT,
+        T::ImpactReport() => &IMPACT_REPORT,
+        T::StreamUpdate() => &STREAM_UPDATE,
+        T::WriteMode() => &MODE,
+        T::ReadVersionResponse() => &VERSION,
+        T::Vendor(_) => &VENDOR_DATA,
+        T::BatteryReport() => &BATTERY_REPORT,
+        T::SetDeviceName() => &NAME,
+        T::SetDeviceNameResponse() => &RESULT,
+        T::ReadCapabilitiesResponse() => &CAPABILITIES,
+        T::ObjectReportRequest()
+        | T::FlashSettings()
+        | T::Ack()
+        | T::ReadVersion()
+        | T::ReadCapabilities()
+        | T::End()
+        | T::VendorStart()
+        | T::VendorEnd() => return None,
+    })
+}
+
+/// Groups the bytes `f` maps to the same value into inclusive ranges.
+fn runs<T: PartialEq>(f: impl Fn(u8) -> Option<T>) -> Vec<(u8, u8, T)> {
+    let mut runs: Vec<(u8, u8, T)> = Vec::new();
+    for n in 0..=u8::MAX {
+        let Some(v) = f(n) else { continue };
+        match runs.last_mut() {
+            Some((_, hi, last)) if *hi + 1 == n && *last == v => *hi = n,
+            _ => runs.push((n, n, v)),
+        }
+    }
+    runs
+}
+
+/// Writes `table[n] = value` for each run, as a loop for longer ones.
+fn write_runs<T: Display>(out: &mut impl Write, table: &str, runs: &[(u8, u8, T)]) -> fmt::Result {
+    for (lo, hi, v) in runs {
+        if lo == hi {
+            writeln!(out, "{table}[0x{lo:02x}] = {v}")?;
+        } else {
+            writeln!(
+                out,
+                "for n = 0x{lo:02x}, 0x{hi:02x} do {table}[n] = {v} end"
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// The layouts of all payloads, each after the layouts it repeats.
+fn layouts() -> Vec<&'static Layout> {
+    fn add(layouts: &mut Vec<&'static Layout>, layout: &'static Layout) {
+        if layouts.iter().any(|l| l.name == layout.name) {
+            return;
+        }
+        for field in layout.fields {
+            if let Field::Repeat(_, _, inner) = field {
+                add(layouts, inner);
+            }
+        }
+        layouts.push(layout);
+    }
+    let mut layouts = Vec::new();
+    for n in 0..=u8::MAX {
+        if let Some(layout) = PacketType::try_from(n).ok().and_then(payload) {
+            add(&mut layouts, layout);
+        }
+    }
+    layouts
+}
+
+fn proto_field(out: &mut impl Write, key: &str, ctor: &str, args: &str) -> fmt::Result {
+    let label = key.rsplit('.').next().unwrap_or(key);
+    writeln!(
+        out,
+        "f[\"{key}\"] = ProtoField.{ctor}(\"protodongers.{key}\", \"{label}\"{args})"
+    )
+}
+
+fn uint_ctor(len: usize) -> String {
+    format!("uint{}", len * 8)
+}
+
+fn register_fields(out: &mut impl Write, prefix: &str, fields: &[Field]) -> fmt::Result {
+    for field in fields {
+        match *field {
+            Field::Uint {
+                name,
+                len,
+                hex,
+                values,
+            } => {
+                let base = if hex { "HEX" } else { "DEC" };
+                let values = values.map_or("nil", Values::table);
+                let args = format!(", base.{base}, {values}");
+                proto_field(out, &format!("{prefix}.{name}"), &uint_ctor(len), &args)?;
+            }
+            Field::Bits { len, fields } => {
+                for (name, mask) in fields {
+                    let args = format!(", base.DEC, nil, 0x{mask:x}");
+                    proto_field(out, &format!("{prefix}.{name}"), &uint_ctor(len), &args)?;
+                }
+            }
+            Field::I16 { name, .. } => {
+                proto_field(out, &format!("{prefix}.{name}"), "int16", ", base.DEC")?
+            }
+            Field::F32 { name, .. } => proto_field(out, &format!("{prefix}.{name}"), "float", "")?,
+            Field::Bool(name) => proto_field(out, &format!("{prefix}.{name}"), "bool", "")?,
+            Field::Bytes(name, _) => proto_field(out, &format!("{prefix}.{name}"), "bytes", "")?,
+            Field::Str(name, _) => proto_field(out, &format!("{prefix}.{name}"), "string", "")?,
+            Field::Points(name, _) => {
+                for axis in ["x", "y"] {
+                    let key = format!("{prefix}.{name}.{axis}");
+                    proto_field(out, &key, "uint16", ", base.DEC")?;
+                }
+            }
+            Field::Switch { cases, .. } => {
+                for case in cases {
+                    register_fields(out, &format!("{prefix}.{}", case.name), case.fields)?;
+                }
+            }
+            Field::Pad(_) | Field::Repeat(..) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Writes the statements adding `fields`, which start `start` bytes into the
+/// payload at `offset`.
+fn dissect_fields(
+    out: &mut impl Write,
+    prefix: &str,
+    fields: &[Field],
+    indent: &str,
+    start: usize,
+) -> fmt::Result {
+    let mut at = start;
+    for field in fields {
+        let range = |len| format!("tvb(offset + {at}, {len})");
+        match *field {
+            Field::Uint { name, len, .. } => writeln!(
+                out,
+                "{indent}tree:add_le(f[\"{prefix}.{name}\"], {})",
+                range(len)
+            )?,
+            Field::Bits { len, fields } => {
+                for (name, _) in fields {
+                    writeln!(
+                        out,
+                        "{indent}tree:add_le(f[\"{prefix}.{name}\"], {})",
+                        range(len)
+                    )?;
+                }
+            }
+            Field::I16 { name, scale: None } => writeln!(
+                out,
+                "{indent}tree:add_le(f[\"{prefix}.{name}\"], {})",
+                range(2)
+            )?,
+            Field::I16 {
+                name,
+                scale: Some((per_unit, unit)),
+            } => writeln!(
+                out,
+                "{indent}tree:add_le(f[\"{prefix}.{name}\"], {r}):append_text(string.format(\" (%.3f {unit})\", {r}:le_int() / {per_unit}))",
+                r = range(2),
+            )?,
+            Field::F32 { name, count: 1 } => writeln!(
+                out,
+                "{indent}tree:add_le(f[\"{prefix}.{name}\"], {})",
+                range(4)
+            )?,
+            Field::F32 { name, count } => writeln!(
+                out,
+                "{indent}add_f32s(tvb, tree, offset + {at}, {count}, f[\"{prefix}.{name}\"])"
+            )?,
+            Field::Bool(name) => writeln!(
+                out,
+                "{indent}tree:add(f[\"{prefix}.{name}\"], {})",
+                range(1)
+            )?,
+            Field::Bytes(name, len) => writeln!(
+                out,
+                "{indent}tree:add(f[\"{prefix}.{name}\"], {})",
+                range(len)
+            )?,
+            Field::Str(name, len) => writeln!(
+                out,
+                "{indent}tree:add(f[\"{prefix}.{name}\"], {r}, {r}:stringz())",
+                r = range(len),
+            )?,
+            Field::Pad(_) => {}
+            Field::Points(name, count) => writeln!(
+                out,
+                "{indent}add_points(tvb, tree, offset + {at}, {count}, \"{name}\", f[\"{prefix}.{name}.x\"], f[\"{prefix}.{name}.y\"])"
+            )?,
+            Field::Repeat(name, count, layout) => writeln!(
+                out,
+                "{indent}add_repeated(tvb, tree, offset + {at}, {count}, {}, \"{name}\", dissect_{})",
+                layout.size(),
+                layout.name,
+            )?,
+            Field::Switch { on, cases, .. } => {
+                writeln!(out, "{indent}local sel = tvb(offset + {on}, 1):uint()")?;
+                for (i, case) in cases.iter().enumerate() {
+                    let keyword = if i == 0 { "if" } else { "elseif" };
+                    writeln!(out, "{indent}{keyword} sel == {} then", case.value)?;
+                    let prefix = format!("{prefix}.{}", case.name);
+                    dissect_fields(out, &prefix, case.fields, &format!("{indent}    "), at)?;
+                }
+                writeln!(out, "{indent}end")?;
+            }
+        }
+        at += field.size();
+    }
+    Ok(())
+}
+
+const PRELUDE: &str = r#"local proto = Proto("protodongers", "Protodongers")
+
+local function add_f32s(tvb, tree, offset, count, field)
+    for i = 0, count - 1 do
+        tree:add_le(field, tvb(offset + i * 4, 4)):prepend_text(string.format("[%d] ", i))
+    end
+end
+
+-- x and y are 12 bits each: x = b0 | (b1 & 0x0f) << 8, y = b1 >> 4 | b2 << 4
+local function add_points(tvb, tree, offset, count, name, fx, fy)
+    for i = 0, count - 1 do
+        local o = offset + i * 3
+        local b0, b1, b2 = tvb(o, 1):uint(), tvb(o + 1, 1):uint(), tvb(o + 2, 1):uint()
+        local x = b0 + (b1 % 16) * 256
+        local y = math.floor(b1 / 16) + b2 * 16
+        local point = tree:add(tvb(o, 3), string.format("%s[%d]: (%d, %d)", name, i, x, y))
+        point:add(fx, tvb(o, 2), x)
+        point:add(fy, tvb(o + 1, 2), y)
+    end
+end
+
+local function add_repeated(tvb, tree, offset, count, size, name, dissect)
+    for i = 0, count - 1 do
+        local o = offset + i * size
+        dissect(tvb, tree:add(tvb(o, size), string.format("%s[%d]", name, i)), o)
+    end
+end
+"#;
+
+const DISSECTOR: &str = r#"
+function proto.dissector(tvb, pinfo, tree)
+    pinfo.cols.protocol = "PROTODONGERS"
+    pinfo.cols.info:clear()
+    local offset = 0
+    -- [words_le, ty, id], where words counts the whole packet in 16-bit words
+    while offset + 4 <= tvb:len() do
+        local len = tvb(offset, 2):le_uint() * 2
+        if len < 4 or offset + len > tvb:len() then
+            break
+        end
+        local ty = tvb(offset + 2, 1):uint()
+        local name = vs_packet_type[ty] or string.format("Unknown (0x%02x)", ty)
+        local sub = tree:add(proto, tvb(offset, len), "Protodongers " .. name)
+        sub:add_le(f["words"], tvb(offset, 2))
+        sub:add(f["type"], tvb(offset + 2, 1))
+        sub:add(f["id"], tvb(offset + 3, 1))
+        if payloads[ty] ~= nil then
+            payloads[ty](tvb, sub, offset + 4)
+        end
+        pinfo.cols.info:append(name .. " ")
+        offset = offset + len
+    end
+    return offset
+end
+
+DissectorTable.get("wtap_encap"):add(wtap_encaps.USER0, proto)
+DissectorTable.get("usb.bulk"):add_for_decode_as(proto)
+"#;
+
+impl Display for LuaDissector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "-- Wireshark dissector for protodongers {}.",
+            env!("CARGO_PKG_VERSION")
+        )?;
+        writeln!(
+            f,
+            "-- Generated by `cargo run --example wireshark_dissector`; do not edit."
+        )?;
+        writeln!(f)?;
+        f.write_str(PRELUDE)?;
+
+        for values in Values::ALL {
+            writeln!(f)?;
+            let table = values.table();
+            writeln!(f, "local {table} = {{}}")?;
+            write_runs(
+                f,
+                table,
+                &runs(|n| values.name(n).map(|v| format!("\"{v}\""))),
+            )?;
+        }
+
+        let layouts = layouts();
+        writeln!(f)?;
+        writeln!(f, "local f = {{}}")?;
+        proto_field(f, "words", "uint16", ", base.DEC")?;
+        proto_field(f, "type", "uint8", ", base.HEX, vs_packet_type")?;
+        proto_field(f, "id", "uint8", ", base.DEC")?;
+        for layout in &layouts {
+            register_fields(f, layout.name, layout.fields)?;
+        }
+        writeln!(f, "local fields = {{}}")?;
+        writeln!(
+            f,
+            "for _, field in pairs(f) do table.insert(fields, field) end"
+        )?;
+        writeln!(f, "proto.fields = fields")?;
+
+        for layout in &layouts {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "local function dissect_{}(tvb, tree, offset)",
+                layout.name
+            )?;
+            dissect_fields(f, layout.name, layout.fields, "    ", 0)?;
+            writeln!(f, "end")?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "local payloads = {{}}")?;
+        let payloads = runs(|n| {
+            let layout = PacketType::try_from(n).ok().and_then(payload)?;
+            Some(format!("dissect_{}", layout.name))
+        });
+        write_runs(f, "payloads", &payloads)?;
+
+        f.write_str(DISSECTOR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Packet, PacketData, tests::samples};
+
+    /// A field's value as the dissector shows it.
+    #[derive(Clone, Debug, PartialEq)]
+    enum Value {
+        Uint(u64),
+        I16(i16),
+        F32(Vec<f32>),
+        Bool(bool),
+        Bytes(Vec<u8>),
+        Str(String),
+        Points(Vec<[u16; 2]>),
+    }
+
+    /// A field's key, offset into the payload and value.
+    type Leaf = (String, usize, Value);
+
+    /// Walks `fields` over `payload` like the generated Lua does. The layout
+    /// the fields belong to starts at `base` and the fields at `at`. Repeated
+    /// layouts are keyed by their index rather than the inner layout's name.
+    fn walk(
+        prefix: &str,
+        fields: &[Field],
+        payload: &[u8],
+        base: usize,
+        mut at: usize,
+        out: &mut Vec<Leaf>,
+    ) {
+        for field in fields {
+            let b = &payload[at..at + field.size()];
+            let uint = b.iter().rev().fold(0, |v, &x| v << 8 | u64::from(x));
+            let mut push = |name: &str, v| out.push((format!("{prefix}.{name}"), at, v));
+            match *field {
+                Field::Uint { name, values, .. } => {
+                    if let Some(values) = values {
+                        assert!(values.name(b[0]).is_some(), "{prefix}.{name} unnamed");
+                    }
+                    push(name, Value::Uint(uint));
+                }
+                Field::Bits { fields, .. } => {
+                    for (name, mask) in fields {
+                        push(name, Value::Uint((uint & mask) >> mask.trailing_zeros()));
+                    }
+                }
+                Field::I16 { name, .. } => push(name, Value::I16(uint as i16)),
+                Field::F32 { name, .. } => {
+                    let floats = b
+                        .chunks(4)
+                        .map(|c| f32::from_le_bytes(c.try_into().unwrap()));
+                    push(name, Value::F32(floats.collect()));
+                }
+                Field::Bool(name) => push(name, Value::Bool(b[0] != 0)),
+                Field::Bytes(name, _) => push(name, Value::Bytes(b.to_vec())),
+                Field::Str(name, _) => {
+                    let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+                    let s = core::str::from_utf8(&b[..end]).unwrap();
+                    push(name, Value::Str(s.into()));
+                }
+                Field::Pad(_) => assert!(b.iter().all(|&c| c == 0), "{prefix} pad at {at}"),
+                Field::Points(name, _) => {
+                    let points = b.chunks(3).map(|c| {
+                        let [b0, b1, b2] = [c[0], c[1], c[2]].map(u16::from);
+                        [b0 | (b1 & 0x0f) << 8, b1 >> 4 | b2 << 4]
+                    });
+                    push(name, Value::Points(points.collect()));
+                }
+                Field::Repeat(name, count, layout) => {
+                    for i in 0..count {
+                        let o = at + i * layout.size();
+                        walk(
+                            &format!("{prefix}.{name}[{i}]"),
+                            layout.fields,
+                            payload,
+                            o,
+                            o,
+                            out,
+                        );
+                    }
+                }
+                Field::Switch { on, len, cases } => {
+                    let sel = payload[base + on];
+                    let case = cases.iter().find(|c| c.value == sel);
+                    let case = case.unwrap_or_else(|| panic!("{prefix}: no case for {sel}"));
+                    let prefix = format!("{prefix}.{}", case.name);
+                    walk(&prefix, case.fields, payload, base, at, out);
+                    let used: usize = case.fields.iter().map(Field::size).sum();
+                    assert!(b[used..len].iter().all(|&c| c == 0), "{prefix} pad");
+                }
+            }
+            at += field.size();
+        }
+    }
+
+    fn uint(key: &str, at: usize, v: impl Into<u64>) -> Leaf {
+        (key.into(), at, Value::Uint(v.into()))
+    }
+
+    fn f32s(key: &str, at: usize, v: &[f32]) -> Leaf {
+        (key.into(), at, Value::F32(v.to_vec()))
+    }
+
+    fn name(key: &str, at: usize, s: &heapless::String<32>) -> [Leaf; 2] {
+        [
+            uint(&format!("{key}.len"), at, s.len() as u64),
+            (format!("{key}.name"), at + 1, Value::Str(s.as_str().into())),
+        ]
+    }
+
+    fn points(key: &str, at: usize, points: &[nalgebra::Point2<u16>]) -> Leaf {
+        let points = points.iter().map(|p| [p.x, p.y]).collect();
+        (key.into(), at, Value::Points(points))
+    }
+
+    fn mot_data(key: &str, at: usize, m: &MotData) -> Vec<Leaf> {
+        [
+            ("area", 0, u64::from(m.area)),
+            ("cx", 2, m.cx.into()),
+            ("cy", 4, m.cy.into()),
+            ("avg_brightness", 6, m.avg_brightness.into()),
+            ("max_brightness", 7, m.max_brightness.into()),
+            ("radius", 8, m.radius.into()),
+            ("range", 8, m.range.into()),
+            ("boundary_left", 9, m.boundary_left.into()),
+            ("boundary_right", 10, m.boundary_right.into()),
+            ("boundary_up", 11, m.boundary_up.into()),
+            ("boundary_down", 12, m.boundary_down.into()),
+            ("aspect_ratio", 13, m.aspect_ratio.into()),
+            ("vx", 14, m.vx.into()),
+            ("vy", 15, m.vy.into()),
+        ]
+        .into_iter()
+        .map(|(name, o, v)| uint(&format!("{key}.{name}"), at + o, v))
+        .collect()
+    }
+
+    /// Every field of the payload of `data`, from the packet's own values.
+    fn expected(data: &PacketData) -> Vec<Leaf> {
+        match data {
+            PacketData::WriteRegister(r) => Vec::from([
+                uint("write_register.port", 0, r.port as u8),
+                uint("write_register.bank", 1, r.bank),
+                uint("write_register.address", 2, r.address),
+                uint("write_register.data", 3, r.data),
+            ]),
+            PacketData::ReadRegister(r) => Vec::from([
+                uint("register.port", 0, r.port as u8),
+                uint("register.bank", 1, r.bank),
+                uint("register.address", 2, r.address),
+            ]),
+            PacketData::ReadRegisterResponse(r) => Vec::from([
+                uint("read_register_response.bank", 0, r.bank),
+                uint("read_register_response.address", 1, r.address),
+                uint("read_register_response.data", 2, r.data),
+            ]),
+            PacketData::WriteConfig(GeneralConfig::AccelConfig(c)) => {
+                let key = "general_config.accel_config";
+                Vec::from([
+                    uint("general_config.kind", 0, ConfigKind::AccelConfig as u8),
+                    uint(&format!("{key}.accel_odr"), 2, c.accel_odr),
+                    f32s(&format!("{key}.b_x"), 4, &[c.b_x]),
+                    f32s(&format!("{key}.b_y"), 8, &[c.b_y]),
+                    f32s(&format!("{key}.b_z"), 12, &[c.b_z]),
+                    f32s(&format!("{key}.s_x"), 16, &[c.s_x]),
+                    f32s(&format!("{key}.s_y"), 20, &[c.s_y]),
+                    f32s(&format!("{key}.s_z"), 24, &[c.s_z]),
+                ])
+            }
+            PacketData::ReadConfig(kind) => Vec::from([uint("config_kind.kind", 0, *kind as u8)]),
+            PacketData::ReadConfigResponse(GeneralConfig::GyroConfig(c)) => Vec::from([
+                uint("general_config.kind", 0, ConfigKind::GyroConfig as u8),
+                f32s("general_config.gyro_config.b_x", 2, &[c.b_x]),
+                f32s("general_config.gyro_config.b_y", 6, &[c.b_y]),
+                f32s("general_config.gyro_config.b_z", 10, &[c.b_z]),
+            ]),
+            PacketData::ReadProp(kind) => Vec::from([uint("prop_kind.kind", 0, *kind as u8)]),
+            PacketData::ReadPropResponse(Props::Name(n)) => {
+                let mut leaves = Vec::from([uint("props.kind", 0, PropKind::Name as u8)]);
+                leaves.extend(name("props.name", 2, n));
+                leaves
+            }
+            PacketData::ObjectReport(r) => {
+                let mut leaves = Vec::from([uint("object_report.timestamp", 0, r.timestamp)]);
+                for (i, m) in r.mot_data_nf.iter().enumerate() {
+                    let key = format!("object_report.mot_data_nf[{i}]");
+                    leaves.extend(mot_data(&key, 4 + 16 * i, m));
+                }
+                for (i, m) in r.mot_data_wf.iter().enumerate() {
+                    let key = format!("object_report.mot_data_wf[{i}]");
+                    leaves.extend(mot_data(&key, 260 + 16 * i, m));
+                }
+                leaves.push(uint("object_report.format", 516, 1u8));
+                leaves
+            }
+            PacketData::CombinedMarkersReport(r) => Vec::from([
+                points("combined_markers_report.nf_points", 0, &r.nf_points),
+                points("combined_markers_report.wf_points", 48, &r.wf_points),
+            ]),
+            PacketData::PocMarkersReport(r) => {
+                Vec::from([points("poc_markers_report.points", 0, &r.points)])
+            }
+            PacketData::AccelReport(r) => {
+                // accel: 2048 = 1g, gyro: 16.4 = 1dps
+                let accel = r.accel.map(|a| (a / 9.80665 * 2048.0).round() as i16);
+                let gyro = r.gyro.map(|g| (g.to_degrees() * 16.4).round() as i16);
+                let mut leaves = Vec::from([uint("accel_report.timestamp", 0, r.timestamp)]);
+                let axes = [
+                    "accel_x", "accel_y", "accel_z", "gyro_x", "gyro_y", "gyro_z",
+                ];
+                for (i, (axis, v)) in axes.iter().zip(accel.iter().chain(&gyro)).enumerate() {
+                    let key = format!("accel_report.{axis}");
+                    leaves.push((key, 4 + 2 * i, Value::I16(*v)));
+                }
+                leaves
+            }
+            PacketData::ImpactReport(r) => {
+                Vec::from([uint("impact_report.timestamp", 0, r.timestamp)])
+            }
+            PacketData::StreamUpdate(u) => Vec::from([
+                uint("stream_update.packet_id", 0, u8::from(u.packet_id)),
+                uint("stream_update.action", 1, u.action as u8),
+            ]),
+            PacketData::WriteMode(mode) => Vec::from([uint("mode.mode", 0, *mode as u8)]),
+            PacketData::ReadVersionResponse(v) => {
+                let semver = v.protocol_semver.iter().chain(&v.firmware_semver);
+                let parts = ["protocol", "firmware"]
+                    .into_iter()
+                    .flat_map(|p| ["major", "minor", "patch"].map(|n| format!("version.{p}_{n}")));
+                parts
+                    .zip(semver)
+                    .enumerate()
+                    .map(|(i, (key, &v))| uint(&key, 2 * i, v))
+                    .collect()
+            }
+            PacketData::BatteryReport(r) => Vec::from([
+                uint("battery_report.percent", 0, r.percent),
+                ("battery_report.charging".into(), 1, Value::Bool(r.charging)),
+            ]),
+            PacketData::SetDeviceName(n) => name("name", 0, n).into(),
+            PacketData::SetDeviceNameResponse(r) => {
+                Vec::from([uint("result.result", 0, r.is_err())])
+            }
+            PacketData::ReadCapabilitiesResponse(c) => {
+                let bytes = |set: &crate::PacketTypeSet| {
+                    Value::Bytes(set.bits.iter().flat_map(|w| w.to_le_bytes()).collect())
+                };
+                Vec::from([
+                    (
+                        "capabilities.packet_types".into(),
+                        0,
+                        bytes(&c.packet_types),
+                    ),
+                    ("capabilities.streams".into(), 32, bytes(&c.streams)),
+                    uint("capabilities.config_kinds", 64, c.config_kinds),
+                    uint("capabilities.transports", 68, c.transports),
+                    (
+                        "capabilities.control_endpoint".into(),
+                        69,
+                        Value::Bool(c.control_endpoint),
+                    ),
+                ])
+            }
+            PacketData::Vendor(_, v) => Vec::from([
+                uint("vendor_data.len", 0, v.len),
+                ("vendor_data.data".into(), 1, Value::Bytes(v.data.to_vec())),
+            ]),
+            PacketData::ObjectReportRequest()
+            | PacketData::FlashSettings()
+            | PacketData::Ack()
+            | PacketData::ReadVersion()
+            | PacketData::ReadCapabilities() => Vec::new(),
+            data => panic!("no expected fields for {data:?}"),
+        }
+    }
+
+    #[test]
+    fn layouts_match_serialized_packets() {
+        for data in samples() {
+            let ty = data.ty();
+            let mut buf = Vec::new();
+            Packet {
+                data: data.clone(),
+                id: 0,
+            }
+            .serialize_to_vec(&mut buf);
+            let bytes = &buf[4..];
+            let mut leaves = Vec::new();
+            match payload(ty) {
+                Some(layout) => {
+                    assert_eq!(layout.size(), bytes.len(), "{ty:?}");
+                    walk(layout.name, layout.fields, bytes, 0, 0, &mut leaves);
+                }
+                None => assert!(bytes.is_empty(), "{ty:?} has no layout"),
+            }
+            assert_eq!(leaves, expected(&data), "{ty:?}");
+        }
+    }
+
+    #[test]
+    fn dissector_covers_every_packet_type() {
+        let lua = lua_dissector();
+        for n in 0..=u8::MAX {
+            let Ok(ty) = PacketType::try_from(n) else {
+                continue;
+            };
+            let name = Values::PacketType.name(n).unwrap();
+            assert!(lua.contains(&format!("= \"{name}\"")), "{ty:?} unnamed");
+            if let Some(layout) = payload(ty) {
+                let dissect = format!("local function dissect_{}(", layout.name);
+                assert!(lua.contains(&dissect), "{ty:?} not dissected");
+            }
+        }
+    }
+}